@@ -0,0 +1,187 @@
+use crate::annotation::{escape, Annotation};
+
+/// One of the built-in admonition classes, enabled with `admonitions = true`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kind {
+    pub class: &'static str,
+    /// The heading used when the annotation has no `title`.
+    pub title: &'static str,
+    /// The Font Awesome 4 icon, which mdbook's html renderer ships with.
+    pub icon: &'static str,
+}
+
+pub const KINDS: [Kind; 5] = [
+    Kind {
+        class: "note",
+        title: "Note",
+        icon: "fa-pencil",
+    },
+    Kind {
+        class: "tip",
+        title: "Tip",
+        icon: "fa-lightbulb-o",
+    },
+    Kind {
+        class: "info",
+        title: "Info",
+        icon: "fa-info-circle",
+    },
+    Kind {
+        class: "warning",
+        title: "Warning",
+        icon: "fa-exclamation-triangle",
+    },
+    Kind {
+        class: "danger",
+        title: "Danger",
+        icon: "fa-bolt",
+    },
+];
+
+/// The admonition for the first of `classes` that is one.
+pub fn kind(classes: &[String]) -> Option<&'static Kind> {
+    classes
+        .iter()
+        .find_map(|class| KINDS.iter().find(|kind| kind.class == class))
+}
+
+/// The opening html of an admonition, if `annotation` has an admonition class: the `wrapper`
+/// element with an `admonition` class and a `note` role, followed by the title. The `title`
+/// attribute becomes the heading rather than a tooltip.
+pub(crate) fn open(annotation: &Annotation, wrapper: &str) -> Option<String> {
+    let kind = kind(&annotation.classes)?;
+    let title = annotation.attribute("title").unwrap_or(kind.title);
+
+    let mut element = annotation.clone();
+    element.classes.insert(0, "admonition".to_string());
+    element.attributes.retain(|(name, _)| name != "title");
+    // These are asides rather than alerts: the page isn't changing under the reader.
+    if element.attribute("role").is_none() {
+        element
+            .attributes
+            .push(("role".to_string(), "note".to_string()));
+    }
+    Some(format!(
+        "<{}{}>\n<p class=\"admonition-title\"><i class=\"fa {}\" aria-hidden=\"true\"></i> {}</p>",
+        wrapper,
+        element.html_attributes(),
+        kind.icon,
+        escape(title)
+    ))
+}
+
+/// Styles for the admonitions in each of mdbook's themes, which `init --admonitions` installs.
+pub const CSS: &str = r#"/* Admonitions from mdbook-classy: `{:.note}`, `{:.tip}`, `{:.info}`, `{:.warning}` and
+   `{:.danger}`, with `admonitions = true` under [preprocessor.classy]. */
+
+.admonition {
+    margin: 1.25em 0;
+    padding: 0 1em;
+    border-left: 4px solid var(--admonition-color);
+    border-radius: 4px;
+    background: var(--admonition-background);
+    overflow: auto;
+}
+
+.admonition > .admonition-title {
+    margin: 0 -1em;
+    padding: 0.5em 1em;
+    font-weight: bold;
+    background: var(--admonition-title-background);
+}
+
+.admonition-title > .fa {
+    margin-right: 0.25em;
+    color: var(--admonition-color);
+}
+
+/* light and rust */
+
+.admonition.note {
+    --admonition-color: #2f6fd1;
+    --admonition-background: rgba(47, 111, 209, 0.06);
+    --admonition-title-background: rgba(47, 111, 209, 0.12);
+}
+
+.admonition.tip {
+    --admonition-color: #1a8a55;
+    --admonition-background: rgba(26, 138, 85, 0.06);
+    --admonition-title-background: rgba(26, 138, 85, 0.12);
+}
+
+.admonition.info {
+    --admonition-color: #0d8a9e;
+    --admonition-background: rgba(13, 138, 158, 0.06);
+    --admonition-title-background: rgba(13, 138, 158, 0.12);
+}
+
+.admonition.warning {
+    --admonition-color: #c77700;
+    --admonition-background: rgba(199, 119, 0, 0.06);
+    --admonition-title-background: rgba(199, 119, 0, 0.12);
+}
+
+.admonition.danger {
+    --admonition-color: #c62828;
+    --admonition-background: rgba(198, 40, 40, 0.06);
+    --admonition-title-background: rgba(198, 40, 40, 0.12);
+}
+
+/* coal, navy and ayu */
+
+:is(.coal, .navy, .ayu) .admonition.note {
+    --admonition-color: #6ea8fe;
+    --admonition-background: rgba(110, 168, 254, 0.08);
+    --admonition-title-background: rgba(110, 168, 254, 0.16);
+}
+
+:is(.coal, .navy, .ayu) .admonition.tip {
+    --admonition-color: #4cc38a;
+    --admonition-background: rgba(76, 195, 138, 0.08);
+    --admonition-title-background: rgba(76, 195, 138, 0.16);
+}
+
+:is(.coal, .navy, .ayu) .admonition.info {
+    --admonition-color: #3dc9dc;
+    --admonition-background: rgba(61, 201, 220, 0.08);
+    --admonition-title-background: rgba(61, 201, 220, 0.16);
+}
+
+:is(.coal, .navy, .ayu) .admonition.warning {
+    --admonition-color: #f0a742;
+    --admonition-background: rgba(240, 167, 66, 0.08);
+    --admonition-title-background: rgba(240, 167, 66, 0.16);
+}
+
+:is(.coal, .navy, .ayu) .admonition.danger {
+    --admonition-color: #f26d6d;
+    --admonition-background: rgba(242, 109, 109, 0.08);
+    --admonition-title-background: rgba(242, 109, 109, 0.16);
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation;
+
+    #[test]
+    fn titles_and_roles() {
+        let annotation = annotation::parse("{:.warning #w title=\"Mind <this>\"}").unwrap();
+        assert_eq!(
+            open(&annotation, "div").unwrap(),
+            "<div id=\"w\" class=\"admonition warning\" role=\"note\">\n\
+             <p class=\"admonition-title\"><i class=\"fa fa-exclamation-triangle\" aria-hidden=\"true\"></i> Mind &lt;this&gt;</p>"
+        );
+
+        let annotation = annotation::parse("{:.wide .tip role=complementary}").unwrap();
+        assert_eq!(
+            open(&annotation, "aside").unwrap(),
+            "<aside class=\"admonition wide tip\" role=\"complementary\">\n\
+             <p class=\"admonition-title\"><i class=\"fa fa-lightbulb-o\" aria-hidden=\"true\"></i> Tip</p>"
+        );
+
+        let annotation = annotation::parse("{:.centered}").unwrap();
+        assert_eq!(open(&annotation, "div"), None);
+    }
+}
@@ -0,0 +1,385 @@
+use std::fmt;
+
+/// The contents of a kramdown-style annotation such as `{:.red big #intro lang="en"}`.
+///
+/// Classes may be written as `.class` tokens or, for compatibility with earlier releases,
+/// as bare words following the first class (`{:.red big}`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Annotation {
+    pub classes: Vec<String>,
+    pub id: Option<String>,
+    pub attributes: Vec<(String, String)>,
+}
+
+/// Why an annotation could not be parsed, and how to fix it when that's clear.
+/// The offset is a byte offset into the annotation text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub offset: usize,
+    pub fix: Option<String>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Does this line look like it was meant to be an annotation?
+/// Kramdown extensions (`{::comment}`) are not annotations.
+pub fn is_annotation(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("{:") && !line.starts_with("{::")
+}
+
+/// Parse an annotation line such as `{:.red big}`.
+pub fn parse(text: &str) -> Result<Annotation, ParseError> {
+    let text = text.trim_end();
+    let inner = match text.strip_prefix("{:") {
+        Some(rest) => rest,
+        None => return Err(error("annotations must start with `{:`", 0)),
+    };
+    let inner = match inner.strip_suffix('}') {
+        Some(inner) => inner,
+        None => {
+            return Err(error("unclosed annotation, expected `}`", text.len())
+                .with_fix("add a closing `}`"))
+        }
+    };
+
+    let mut annotation = Annotation::default();
+    let mut tokens = Tokens {
+        text: inner,
+        pos: 0,
+    };
+    while let Some((start, token)) = tokens.next_token()? {
+        // Offsets reported to the caller are relative to the full annotation text.
+        let offset = start + 2;
+        match token {
+            Token::Class(class) => {
+                validate_name(class, offset, "class")?;
+                annotation.classes.push(class.to_string());
+            }
+            Token::Id(id) => {
+                validate_name(id, offset, "id")?;
+                if annotation.id.is_some() {
+                    return Err(error("an annotation may only have one `#id`", offset)
+                        .with_fix(format!("remove `#{}`", id)));
+                }
+                annotation.id = Some(id.to_string());
+            }
+            Token::Attribute(name, value) => {
+                validate_attribute_name(name, offset)?;
+                annotation.attributes.push((name.to_string(), value));
+            }
+            Token::Word(word) => {
+                if annotation.classes.is_empty() {
+                    return Err(error(
+                        format!("expected `.class`, `#id` or `key=value`, found `{}`", word),
+                        offset,
+                    )
+                    .with_fix(format!("write `.{}`", word)));
+                }
+                validate_name(word, offset, "class")?;
+                annotation.classes.push(word.to_string());
+            }
+        }
+    }
+
+    if annotation.classes.is_empty() && annotation.id.is_none() && annotation.attributes.is_empty()
+    {
+        return Err(error("empty annotation", 0).with_fix("remove the annotation"));
+    }
+    Ok(annotation)
+}
+
+/// Returns the reason an attribute is considered unsafe to pass through to the html.
+pub fn unsafe_attribute(name: &str, value: &str) -> Option<&'static str> {
+    let value = value.trim().to_ascii_lowercase();
+    if name.to_ascii_lowercase().starts_with("on") {
+        Some("event handler attributes are not allowed")
+    } else if value.starts_with("javascript:") || value.starts_with("vbscript:") {
+        Some("script urls are not allowed")
+    } else {
+        None
+    }
+}
+
+impl Annotation {
+    /// The value of the attribute `name`, if it's set.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Render the annotation as html attributes, e.g. ` id="intro" class="red big"`.
+    /// Unsafe attributes are skipped.
+    pub fn html_attributes(&self) -> String {
+        let mut html = String::new();
+        if let Some(id) = &self.id {
+            html.push_str(&format!(" id=\"{}\"", escape(id)));
+        }
+        if !self.classes.is_empty() {
+            html.push_str(&format!(" class=\"{}\"", escape(&self.classes.join(" "))));
+        }
+        for (name, value) in &self.attributes {
+            if unsafe_attribute(name, value).is_none() {
+                html.push_str(&format!(" {}=\"{}\"", name, escape(value)));
+            }
+        }
+        html
+    }
+}
+
+/// The canonical annotation syntax: `{:.class .other #id key="value"}`.
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tokens: Vec<String> = self.classes.iter().map(|c| format!(".{}", c)).collect();
+        if let Some(id) = &self.id {
+            tokens.push(format!("#{}", id));
+        }
+        for (name, value) in &self.attributes {
+            if !value.contains('"') {
+                tokens.push(format!("{}=\"{}\"", name, value));
+            } else if !value.contains('\'') {
+                tokens.push(format!("{}='{}'", name, value));
+            } else {
+                // Neither quote can hold a value with both, so it can only have been unquoted.
+                // Values with spaces as well can't be written at all; see `round_trips`.
+                tokens.push(format!("{}={}", name, value));
+            }
+        }
+        write!(f, "{{:{}}}", tokens.join(" "))
+    }
+}
+
+impl Annotation {
+    /// Whether the annotation's canonical syntax parses back to the same annotation. It doesn't
+    /// when an attribute value has both kinds of quote and a space, which no syntax can hold.
+    pub fn round_trips(&self) -> bool {
+        parse(&self.to_string()).as_ref() == Ok(self)
+    }
+}
+
+/// Escape text for use inside a double-quoted html attribute.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+enum Token<'a> {
+    Class(&'a str),
+    Id(&'a str),
+    Attribute(&'a str, String),
+    Word(&'a str),
+}
+
+struct Tokens<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next_token(&mut self) -> Result<Option<(usize, Token<'a>)>, ParseError> {
+        let rest = &self.text[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        if trimmed.is_empty() {
+            return Ok(None);
+        }
+
+        let start = self.pos;
+        if let Some(class) = trimmed.strip_prefix('.') {
+            let class = self.take_word(1, class);
+            return Ok(Some((start, Token::Class(class))));
+        }
+        if let Some(id) = trimmed.strip_prefix('#') {
+            let id = self.take_word(1, id);
+            return Ok(Some((start, Token::Id(id))));
+        }
+
+        let end = trimmed
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(trimmed.len());
+        let name = &trimmed[..end];
+        if !trimmed[end..].starts_with('=') {
+            self.pos += end;
+            return Ok(Some((start, Token::Word(name))));
+        }
+
+        self.pos += end + 1;
+        let value = self.take_value(start)?;
+        Ok(Some((start, Token::Attribute(name, value))))
+    }
+
+    /// Consume a word that starts `prefix` bytes into the remaining text.
+    fn take_word(&mut self, prefix: usize, text: &'a str) -> &'a str {
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        self.pos += prefix + end;
+        &text[..end]
+    }
+
+    fn take_value(&mut self, start: usize) -> Result<String, ParseError> {
+        let rest = &self.text[self.pos..];
+        match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => match rest[1..].find(quote) {
+                Some(end) => {
+                    self.pos += end + 2;
+                    Ok(rest[1..end + 1].to_string())
+                }
+                None => Err(error("unterminated attribute value", start + 2)
+                    .with_fix(format!("add a closing `{}`", quote))),
+            },
+            _ => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                self.pos += end;
+                Ok(rest[..end].to_string())
+            }
+        }
+    }
+}
+
+fn validate_name(name: &str, offset: usize, kind: &str) -> Result<(), ParseError> {
+    if name.is_empty() {
+        return Err(error(format!("empty {} name", kind), offset));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return Err(error(
+            format!("invalid character `{}` in {} name `{}`", c, kind, name),
+            offset,
+        ));
+    }
+    Ok(())
+}
+
+fn validate_attribute_name(name: &str, offset: usize) -> Result<(), ParseError> {
+    let valid_start = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':');
+    let valid_rest = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '.' | '-'));
+    if valid_start && valid_rest {
+        Ok(())
+    } else {
+        Err(error(format!("invalid attribute name `{}`", name), offset))
+    }
+}
+
+fn error(message: impl Into<String>, offset: usize) -> ParseError {
+    ParseError {
+        message: message.into(),
+        offset,
+        fix: None,
+    }
+}
+
+impl ParseError {
+    fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_round_trips() {
+        for text in [
+            "{:.red big #intro lang=en}",
+            "{:title='say \"hi\"'}",
+            "{:title=\"it's\"}",
+            "{:data-x=it's\"odd\"}",
+        ] {
+            let annotation = parse(text).unwrap();
+            assert!(annotation.round_trips(), "{}", text);
+            assert_eq!(parse(&annotation.to_string()).unwrap(), annotation);
+        }
+        assert_eq!(
+            parse("{:data-x=it's\"odd\"}").unwrap().to_string(),
+            "{:data-x=it's\"odd\"}"
+        );
+
+        let unwritable = Annotation {
+            attributes: vec![("title".to_string(), "it's \"both\"".to_string())],
+            ..Annotation::default()
+        };
+        assert!(!unwritable.round_trips());
+    }
+
+    #[test]
+    fn parses_legacy_class_list() {
+        let annotation = parse("{:.red big}").unwrap();
+        assert_eq!(annotation.classes, vec!["red", "big"]);
+        assert_eq!(annotation.html_attributes(), " class=\"red big\"");
+    }
+
+    #[test]
+    fn parses_ids_and_attributes() {
+        let annotation = parse(r#"{: .note #intro title="Read me" lang=en}"#).unwrap();
+        assert_eq!(annotation.classes, vec!["note"]);
+        assert_eq!(annotation.id.as_deref(), Some("intro"));
+        assert_eq!(
+            annotation.attributes,
+            vec![
+                ("title".to_string(), "Read me".to_string()),
+                ("lang".to_string(), "en".to_string())
+            ]
+        );
+        assert_eq!(
+            annotation.html_attributes(),
+            " id=\"intro\" class=\"note\" title=\"Read me\" lang=\"en\""
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_annotations() {
+        assert_eq!(parse("{:.red").unwrap_err().offset, 6);
+        assert_eq!(parse("{:.}").unwrap_err().message, "empty class name");
+        assert!(parse("{:red}").is_err());
+        assert!(parse(r#"{:.red" onclick="alert(1)}"#).is_err());
+        assert!(parse(r#"{:.red title="oops}"#).is_err());
+        assert!(parse("{:#a #b}").is_err());
+    }
+
+    #[test]
+    fn displays_canonical_syntax() {
+        for (input, canonical) in [
+            ("{:.a b}", "{:.a .b}"),
+            ("{: .a .b }", "{:.a .b}"),
+            ("{:#top .a lang=en}", "{:.a #top lang=\"en\"}"),
+            (r#"{:.a title='say "hi"'}"#, r#"{:.a title='say "hi"'}"#),
+        ] {
+            assert_eq!(parse(input).unwrap().to_string(), canonical);
+        }
+    }
+
+    #[test]
+    fn flags_unsafe_attributes() {
+        let annotation = parse(r#"{:.red onclick="alert(1)" href="javascript:x()"}"#).unwrap();
+        for (name, value) in &annotation.attributes {
+            assert!(unsafe_attribute(name, value).is_some());
+        }
+        assert_eq!(annotation.html_attributes(), " class=\"red\"");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::annotation::{self, Annotation};
+use crate::config::Options;
+use crate::diagnostic::{self, Diagnostic};
+use crate::error::ClassyError;
+use crate::preprocessor::{is_off, off_ranges};
+use globset::Glob;
+use pulldown_cmark::{Event, Tag};
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::Path;
+
+/// The classes, id and attributes of a whole chapter, from a `{::chapter .class}` paragraph at
+/// the top of it and the `chapter-classes` that match its path.
+#[derive(Debug, Default)]
+pub(crate) struct ChapterAnnotation {
+    pub annotation: Annotation,
+    /// The events of the `{::chapter}` paragraph, if there is one.
+    pub directive: Option<Range<usize>>,
+}
+
+/// The attributes of a `{::chapter ...}` directive, if `text` is one.
+fn directive(text: &str) -> Option<&str> {
+    let rest = text.trim().strip_prefix("{::chapter")?;
+    (rest.starts_with(char::is_whitespace) || rest == "}").then_some(rest)
+}
+
+/// Find what annotates the chapter at `path` as a whole, besides the `classes` from its front
+/// matter. Malformed directives are reported and left in place.
+pub(crate) fn chapter_annotation(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    path: Option<&Path>,
+    classes: &[String],
+    options: &Options,
+) -> Result<(ChapterAnnotation, Vec<Diagnostic>), ClassyError> {
+    let mut chapter = ChapterAnnotation::default();
+    let mut diagnostics = vec![];
+    if let Some(path) = path {
+        for (pattern, classes) in &options.chapter_classes {
+            let glob = Glob::new(pattern).map_err(|e| {
+                ClassyError::InvalidOption(format!(
+                    "`chapter-classes` has an invalid glob `{}`: {}",
+                    pattern,
+                    e.kind()
+                ))
+            })?;
+            if glob.compile_matcher().is_match(path) {
+                chapter
+                    .annotation
+                    .classes
+                    .extend(classes.split_whitespace().map(String::from));
+            }
+        }
+    }
+    chapter.annotation.classes.extend_from_slice(classes);
+
+    let off = off_ranges(events);
+    if let [(Event::Start(Tag::Paragraph), range), ..] = events {
+        let text = &content[range.clone()];
+        let single_line = text.trim().lines().count() == 1;
+        if let Some(rest) = directive(text).filter(|_| single_line && !is_off(&off, range.start)) {
+            let end = (0..events.len())
+                .find(|&j| matches!(events[j].0, Event::End(Tag::Paragraph)))
+                .unwrap_or(0);
+            let span = range.start..range.start + text.trim_end().len();
+            match annotation::parse(&format!("{{:{}", rest)) {
+                Ok(annotation) => {
+                    chapter.annotation.classes.extend(annotation.classes);
+                    chapter.annotation.id = annotation.id;
+                    chapter.annotation.attributes = annotation.attributes;
+                    chapter.directive = Some(0..end + 1);
+                }
+                Err(e) => {
+                    let mut malformed = Diagnostic::error(
+                        diagnostic::MALFORMED_ANNOTATION,
+                        e.message,
+                        content,
+                        span,
+                    );
+                    malformed.fix = e.fix;
+                    diagnostics.push(malformed);
+                }
+            }
+        }
+    }
+    chapter.annotation.classes = options.resolve_aliases(&chapter.annotation.classes);
+    let mut seen = BTreeSet::new();
+    chapter
+        .annotation
+        .classes
+        .retain(|class| seen.insert(class.clone()));
+    Ok((chapter, diagnostics))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdbook::utils::new_cmark_parser;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn chapter_classes() {
+        let options = Options {
+            chapter_classes: BTreeMap::from([
+                ("appendix/**".to_string(), "appendix".to_string()),
+                ("**/reference.md".to_string(), "reference wide".to_string()),
+            ]),
+            ..Options::default()
+        };
+        let content = "{::chapter .wide #top lang=en}\n\n# Reference\n";
+        let events: Vec<_> = new_cmark_parser(content, false)
+            .into_offset_iter()
+            .collect();
+        let path = Path::new("appendix/a/reference.md");
+        let (chapter, diagnostics) =
+            chapter_annotation(content, &events, Some(path), &[], &options).unwrap();
+        assert_eq!(
+            chapter.annotation.classes,
+            ["reference", "wide", "appendix"]
+        );
+        assert_eq!(chapter.annotation.id.as_deref(), Some("top"));
+        assert_eq!(chapter.directive, Some(0..3));
+        assert!(diagnostics.is_empty());
+
+        let (chapter, _) = chapter_annotation(
+            content,
+            &events,
+            Some(Path::new("intro.md")),
+            &["front".to_string()],
+            &options,
+        )
+        .unwrap();
+        assert_eq!(chapter.annotation.classes, ["front", "wide"]);
+
+        let options = Options {
+            chapter_classes: BTreeMap::from([("[".to_string(), "x".to_string())]),
+            ..Options::default()
+        };
+        assert!(chapter_annotation(content, &events, Some(path), &[], &options).is_err());
+    }
+}
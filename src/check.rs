@@ -0,0 +1,130 @@
+use crate::chapter::chapter_annotation;
+use crate::config::Options;
+use crate::container::scan_containers;
+use crate::css;
+use crate::diagnostic::{self, Diagnostic};
+use crate::front_matter::{self, FrontMatter};
+use crate::numbering::undefined_references;
+use crate::preprocessor::{scan_with_options, BookData};
+use mdbook::book::BookItem;
+use mdbook::errors::Error;
+use mdbook::utils::new_cmark_parser;
+use mdbook::MDBook;
+use std::path::Path;
+
+/// Lint every chapter of the book at `root` without building it.
+///
+/// Reports malformed annotations, container fences and front matter, unsafe or disallowed
+/// attributes, `[@id]` references to blocks that aren't numbered, and classes that no
+/// stylesheet defines. Aliases are checked by the classes they
+/// stand for, and chapters with `classy = false` in their front matter are skipped.
+/// Classes count as defined when they appear in `output.html.additional-css` or in a
+/// `<style>` element in any chapter, when classy styles them itself, like tabs and the
+/// admonitions, or when they're in `exclude-classes`.
+pub fn check(root: &Path) -> Result<Vec<Diagnostic>, Error> {
+    let md = MDBook::load(root)?;
+    let src_dir = md.root.join(&md.config.book.src);
+    let known_classes = css::book_classes(&md)?;
+    let options = Options::from_config(&md.config)?;
+    let data = BookData::from_book(&md.book, &options);
+
+    let mut diagnostics = vec![];
+    for item in md.book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(chapter) => chapter,
+            _ => continue,
+        };
+        let path = match &chapter.source_path {
+            Some(path) => src_dir.join(path),
+            None => continue,
+        };
+        // Chapters can turn classy off, or add aliases, in their front matter.
+        let (content, front_matter, front_matter_diagnostics) =
+            front_matter::split(&chapter.content);
+        diagnostics.extend(front_matter_diagnostics);
+        let content = content.as_str();
+        let options = match &front_matter {
+            Some(front_matter) if !front_matter.classy => {
+                set_path(&mut diagnostics, &path);
+                continue;
+            }
+            Some(front_matter) => front_matter.apply(&options),
+            None => options.clone(),
+        };
+        let events: Vec<_> = new_cmark_parser(content, false)
+            .into_offset_iter()
+            .collect();
+        let (class_annotations, chapter_diagnostics) =
+            scan_with_options(content, &events, &options);
+        let (containers, container_diagnostics) =
+            scan_containers(content, &events, &class_annotations, &options);
+        let chapter_classes = front_matter
+            .as_ref()
+            .map(FrontMatter::classes)
+            .unwrap_or_default();
+        let (chapter_annotation, directive_diagnostics) = chapter_annotation(
+            content,
+            &events,
+            chapter.source_path.as_deref(),
+            &chapter_classes,
+            &options,
+        )?;
+
+        diagnostics.extend(chapter_diagnostics);
+        diagnostics.extend(container_diagnostics);
+        diagnostics.extend(directive_diagnostics);
+        if !options.numbered.is_empty() {
+            let from = chapter.source_path.as_deref();
+            diagnostics.extend(undefined_references(content, &events, |id| {
+                data.references.link(id, from).is_some()
+            }));
+        }
+        // Classes the whole chapter gets are reported at its `{::chapter}`, or at the top.
+        let whole = match &chapter_annotation.directive {
+            Some(directive) => {
+                let range = &events[directive.start].1;
+                range.start..range.start + content[range.clone()].trim_end().len()
+            }
+            None => 0..0,
+        };
+        let annotated = class_annotations
+            .iter()
+            .map(|ca| (&ca.annotation, ca.span.clone()));
+        let annotated = [(&chapter_annotation.annotation, whole)]
+            .into_iter()
+            .chain(annotated);
+        let fenced = containers.iter().map(|container| {
+            let range = &events[container.open.start].1;
+            let span = range.start..range.start + content[range.clone()].trim_end().len();
+            (&container.annotation, span)
+        });
+        for (annotation, span) in annotated.chain(fenced) {
+            for class in &annotation.classes {
+                if !known_classes.contains(class) {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            diagnostic::UNKNOWN_CLASS,
+                            format!("unknown class `{}`: no stylesheet defines it", class),
+                            content,
+                            span.clone(),
+                        )
+                        .with_fix(format!(
+                            "define `.{}` in a stylesheet listed in `output.html.additional-css`",
+                            class
+                        )),
+                    );
+                }
+            }
+        }
+        set_path(&mut diagnostics, &path);
+    }
+    diagnostics.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+    Ok(diagnostics)
+}
+
+/// Point the diagnostics that don't say where they are yet at the chapter at `path`.
+fn set_path(diagnostics: &mut [Diagnostic], path: &Path) {
+    for diagnostic in diagnostics.iter_mut().filter(|d| d.path.is_none()) {
+        diagnostic.path = Some(path.to_path_buf());
+    }
+}
@@ -0,0 +1,92 @@
+use crate::annotation::Annotation;
+
+/// The most columns `cols` can ask for.
+pub(crate) const MAX_COLUMNS: usize = 6;
+
+/// The class of columns containers, which classy styles itself.
+pub(crate) const CLASS: &str = "columns";
+
+/// Whether a container lays out its blocks as columns.
+pub(crate) fn is_columns(annotation: &Annotation) -> bool {
+    annotation.classes.iter().any(|class| class == CLASS)
+}
+
+/// Why the container's `cols` attribute can't be used, if it can't.
+pub(crate) fn invalid_cols(annotation: &Annotation) -> Option<String> {
+    let cols = annotation.attribute("cols")?;
+    match cols.parse::<usize>() {
+        Ok(1..=MAX_COLUMNS) => None,
+        _ => Some(format!(
+            "`cols` should be a number from 1 to {}, found `{}`",
+            MAX_COLUMNS, cols
+        )),
+    }
+}
+
+/// Styles for columns containers. Each block in the container is a column, or with `cols`, the
+/// blocks fill that many columns row by row. Narrow screens get a single column. Only layout is
+/// set, so the columns look right in every theme.
+fn css() -> String {
+    let mut css = vec![
+        "<style>".to_string(),
+        ".columns { display: grid; grid-auto-flow: column; grid-auto-columns: minmax(0, 1fr); column-gap: 1.5em; }".to_string(),
+        ".columns[data-cols] { grid-auto-flow: row; }".to_string(),
+    ];
+    for cols in 1..=MAX_COLUMNS {
+        css.push(format!(
+            ".columns[data-cols=\"{0}\"] {{ grid-template-columns: repeat({0}, minmax(0, 1fr)); }}",
+            cols
+        ));
+    }
+    css.push(
+        "@media (max-width: 600px) { .columns.columns { grid-auto-flow: row; grid-template-columns: minmax(0, 1fr); } }"
+            .to_string(),
+    );
+    css.push("</style>".to_string());
+    css.join("\n")
+}
+
+/// The element opening a columns container, preceded by the styles when `assets`. `cols`
+/// becomes `data-cols`.
+pub(crate) fn open(annotation: &Annotation, wrapper: &str, assets: bool) -> String {
+    let mut element = annotation.clone();
+    for (name, _) in &mut element.attributes {
+        if name == "cols" {
+            *name = "data-cols".to_string();
+        }
+    }
+    let open = format!("<{}{}>", wrapper, element.html_attributes());
+    if assets {
+        format!("{}\n{}", css(), open)
+    } else {
+        open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation;
+
+    #[test]
+    fn columns() {
+        let annotation = annotation::parse("{:.columns cols=3}").unwrap();
+        assert!(is_columns(&annotation));
+        assert_eq!(invalid_cols(&annotation), None);
+        assert_eq!(
+            open(&annotation, "div", false),
+            "<div class=\"columns\" data-cols=\"3\">"
+        );
+        let css = open(&annotation, "div", true);
+        assert!(css.contains(
+            ".columns[data-cols=\"6\"] { grid-template-columns: repeat(6, minmax(0, 1fr)); }"
+        ));
+        assert!(css.ends_with("</style>\n<div class=\"columns\" data-cols=\"3\">"));
+
+        let annotation = annotation::parse("{:.columns cols=many}").unwrap();
+        assert_eq!(
+            invalid_cols(&annotation).unwrap(),
+            "`cols` should be a number from 1 to 6, found `many`"
+        );
+    }
+}
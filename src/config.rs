@@ -0,0 +1,251 @@
+use crate::diagnostic::{self, Diagnostic, Format};
+use crate::handler::{ClassHandler, CommandHandler, Handlers};
+use mdbook::errors::Error;
+use mdbook::preprocess::PreprocessorContext;
+use mdbook::Config;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Settings read from the `[preprocessor.classy]` table of `book.toml`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Options {
+    /// Write the diagnostics from each build to this file, relative to the book root.
+    pub diagnostics_file: Option<PathBuf>,
+    /// Format of `diagnostics-file`: `text`, `json` (one diagnostic per line) or `sarif`.
+    pub diagnostics_format: Format,
+    /// Fail instead of leaving malformed or unsafe annotations in place.
+    pub strict: bool,
+    /// Run even when mdbook's version looks incompatible with the one classy was built against.
+    pub skip_version_check: bool,
+    /// The element annotated blocks are wrapped in.
+    pub wrapper: String,
+    /// The attributes, besides `id` and `class`, passed through to the html. Any safe attribute
+    /// is when this isn't set.
+    pub allowed_attributes: Option<Vec<String>>,
+    /// Render `note`, `tip`, `info`, `warning` and `danger` as admonitions, with a title and icon.
+    pub admonitions: bool,
+    /// Classes rendered as collapsible `<details>` elements.
+    pub collapsible: Vec<String>,
+    /// Classes whose blocks are numbered, with the name to number them by, e.g.
+    /// `theorem = "Theorem"`. Numbered blocks with an id can be referred to with `[@id]`.
+    pub numbered: BTreeMap<String, String>,
+    /// The chapter, by its path in the book's source, that the glossary of `{:.term}` blocks is
+    /// added to the end of. `{{#classy-glossary}}` places it anywhere too.
+    pub glossary_chapter: Option<PathBuf>,
+    /// Link the first use of each glossary term in other chapters to its definition.
+    pub link_terms: bool,
+    /// Classes for whole chapters, by a glob of their paths in the book's source, e.g.
+    /// `"appendix/**" = "appendix"`.
+    pub chapter_classes: BTreeMap<String, String>,
+    /// Classes whose blocks are left out of the book entirely, for every renderer.
+    pub exclude_classes: Vec<String>,
+    /// Classes that stand for others, e.g. `tip = "callout callout-tip"`.
+    pub aliases: BTreeMap<String, String>,
+    /// What to do with annotations for each renderer. Renderers not listed are left alone.
+    pub strategies: BTreeMap<String, Strategy>,
+    /// Commands that render particular classes.
+    #[serde(rename = "handlers")]
+    pub commands: BTreeMap<String, CommandHandler>,
+    /// Handlebars templates that render particular classes, relative to the book root. These
+    /// take precedence over commands for the same class.
+    pub templates: BTreeMap<String, PathBuf>,
+    /// Where to look for a `<class>.hbs` template for each class, relative to the book root.
+    pub template_dir: PathBuf,
+    /// Custom rendering for particular classes, set from code. These take precedence over
+    /// templates and commands for the same class.
+    #[serde(skip)]
+    pub handlers: Handlers,
+}
+
+/// How annotations are handled for a renderer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    /// Wrap annotated blocks in html elements with the annotated classes.
+    Wrap,
+    /// Remove the annotations, leaving the blocks as they are.
+    Strip,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            diagnostics_file: None,
+            diagnostics_format: Format::default(),
+            strict: false,
+            skip_version_check: false,
+            wrapper: "div".to_string(),
+            allowed_attributes: None,
+            admonitions: false,
+            collapsible: vec![],
+            numbered: BTreeMap::new(),
+            glossary_chapter: None,
+            link_terms: false,
+            chapter_classes: BTreeMap::new(),
+            exclude_classes: vec![],
+            aliases: BTreeMap::new(),
+            strategies: BTreeMap::from([("html".to_string(), Strategy::Wrap)]),
+            commands: BTreeMap::new(),
+            templates: BTreeMap::new(),
+            template_dir: PathBuf::from("theme/classy"),
+            handlers: Handlers::default(),
+        }
+    }
+}
+
+impl Options {
+    pub fn from_context(ctx: &PreprocessorContext) -> Result<Options, Error> {
+        Options::from_config(&ctx.config)
+    }
+
+    pub fn from_config(config: &Config) -> Result<Options, Error> {
+        Options::default().merged(config)
+    }
+
+    /// These options, overridden by any set in the `[preprocessor.classy]` table of `config`.
+    /// Tables such as `aliases` are merged key by key.
+    pub fn merged(&self, config: &Config) -> Result<Options, Error> {
+        let mut options = toml::Value::try_from(self)?;
+        // Anything but a table, like the `classy = "classy"` some books have, sets nothing.
+        if let Some(table @ toml::Value::Table(_)) = config.get("preprocessor.classy") {
+            merge(&mut options, table.clone());
+        }
+        Ok(Options {
+            handlers: self.handlers.clone(),
+            ..options.try_into()?
+        })
+    }
+
+    /// The handler for `class`, if it has one.
+    pub fn handler(&self, class: &str) -> Option<&dyn ClassHandler> {
+        self.handlers.get(class).or_else(|| {
+            self.commands
+                .get(class)
+                .map(|command| command as &dyn ClassHandler)
+        })
+    }
+
+    /// Replace any aliases among `classes` with the classes they stand for.
+    pub fn resolve_aliases(&self, classes: &[String]) -> Vec<String> {
+        let mut resolved = vec![];
+        for class in classes {
+            match self.aliases.get(class) {
+                Some(alias) => resolved.extend(alias.split_whitespace().map(String::from)),
+                None => resolved.push(class.clone()),
+            }
+        }
+        resolved
+    }
+
+    /// Write `diagnostics` to `diagnostics-file`, if one is configured.
+    pub fn write_diagnostics(&self, root: &Path, diagnostics: &[Diagnostic]) -> Result<(), Error> {
+        if let Some(file) = &self.diagnostics_file {
+            let mut out = File::create(root.join(file))?;
+            diagnostic::write(&mut out, diagnostics, self.diagnostics_format)?;
+        }
+        Ok(())
+    }
+}
+
+fn merge(base: &mut toml::Value, overrides: toml::Value) {
+    match (base, overrides) {
+        (toml::Value::Table(base), toml::Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Apply a `key=value` assignment to the `[preprocessor.classy]` table, as if it were in `book.toml`.
+/// Values are parsed as TOML, and anything that isn't valid TOML is taken as a string.
+pub fn set_option(config: &mut Config, assignment: &str) -> Result<(), Error> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| Error::msg(format!("expected KEY=VALUE, found `{}`", assignment)))?;
+    let value = toml::from_str::<toml::value::Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+    config.set(format!("preprocessor.classy.{}", key.trim()), value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_preprocessor_table() {
+        let ctx: PreprocessorContext = serde_json::from_value(json!({
+            "root": "/path/to/book",
+            "config": {
+                "book": {"src": "src"},
+                "preprocessor": {
+                    "classy": {
+                        "command": "mdbook-classy",
+                        "diagnostics-file": "classy.sarif",
+                        "diagnostics-format": "sarif"
+                    }
+                }
+            },
+            "renderer": "html",
+            "mdbook_version": "0.4.35"
+        }))
+        .unwrap();
+        let options = Options::from_context(&ctx).unwrap();
+        assert_eq!(
+            options.diagnostics_file,
+            Some(PathBuf::from("classy.sarif"))
+        );
+        assert_eq!(options.diagnostics_format, Format::Sarif);
+    }
+
+    #[test]
+    fn set_option_overrides_config() {
+        let mut config = Config::default();
+        set_option(&mut config, "diagnostics-format=\"json\"").unwrap();
+        set_option(&mut config, "diagnostics-file=out/classy.jsonl").unwrap();
+        let options = Options::from_config(&config).unwrap();
+        assert_eq!(options.diagnostics_format, Format::Json);
+        assert_eq!(
+            options.diagnostics_file,
+            Some(PathBuf::from("out/classy.jsonl"))
+        );
+        assert!(set_option(&mut config, "no-value").is_err());
+    }
+
+    #[test]
+    fn config_merges_over_options() {
+        let base = Options {
+            wrapper: "section".to_string(),
+            strict: true,
+            aliases: BTreeMap::from([("tip".to_string(), "callout tip".to_string())]),
+            ..Options::default()
+        };
+        let mut config = Config::default();
+        set_option(&mut config, "strict=false").unwrap();
+        set_option(&mut config, "aliases={warn = \"callout warning\"}").unwrap();
+        set_option(&mut config, "strategies={markdown = \"strip\"}").unwrap();
+        let options = base.merged(&config).unwrap();
+
+        assert_eq!(options.wrapper, "section");
+        assert!(!options.strict);
+        assert_eq!(options.aliases.len(), 2);
+        assert_eq!(
+            options.resolve_aliases(&["warn".to_string(), "big".to_string()]),
+            vec!["callout", "warning", "big"]
+        );
+        assert_eq!(options.strategies["html"], Strategy::Wrap);
+        assert_eq!(options.strategies["markdown"], Strategy::Strip);
+    }
+}
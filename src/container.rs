@@ -0,0 +1,276 @@
+use crate::annotation::{self, Annotation};
+use crate::columns;
+use crate::config::Options;
+use crate::diagnostic::{self, Diagnostic};
+use crate::preprocessor::{is_off, off_ranges, ClassAnnotation};
+use crate::tabs;
+use pulldown_cmark::{Event, Tag};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// A container in pandoc's fenced div syntax, with each fence a paragraph of its own:
+///
+/// ```markdown
+/// ::: {.tabs}
+///
+/// {:.tab title="Rust"}
+/// Some text.
+///
+/// :::
+/// ```
+#[derive(Debug)]
+pub(crate) struct Container {
+    pub annotation: Annotation,
+    /// The events of the opening fence's paragraph.
+    pub open: Range<usize>,
+    /// The events of the closing fence's paragraph.
+    pub close: Range<usize>,
+    /// Indices of the annotations directly inside the container, rather than in one nested in it.
+    pub children: Vec<usize>,
+}
+
+/// A fence line: `::: {.class}` or `::: class` opens a container, and `:::` closes one.
+/// More colons are fine, before and after the attributes.
+fn fence(line: &str) -> Option<&str> {
+    let rest = line.trim().strip_prefix(":::")?;
+    Some(
+        rest.trim_start_matches(':')
+            .trim()
+            .trim_end_matches(':')
+            .trim_end(),
+    )
+}
+
+/// Find the containers in `events`, with the annotations directly inside each. Fences that
+/// don't pair up are reported and left in place.
+pub(crate) fn scan_containers(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    class_annotations: &[ClassAnnotation],
+    options: &Options,
+) -> (Vec<Container>, Vec<Diagnostic>) {
+    let mut containers = vec![];
+    let mut diagnostics = vec![];
+    let mut open: Vec<(Annotation, Range<usize>, Range<usize>)> = vec![];
+    let off = off_ranges(events);
+    for (i, (event, range)) in events.iter().enumerate() {
+        if !matches!(event, Event::Start(Tag::Paragraph)) || is_off(&off, range.start) {
+            continue;
+        }
+        // A fence inside an annotated block is part of that block.
+        if class_annotations
+            .iter()
+            .any(|ca| ca.start <= i && i <= ca.end)
+        {
+            continue;
+        }
+        let text = &content[range.clone()];
+        let attributes = match text.lines().count() {
+            1 => match fence(text) {
+                Some(attributes) => attributes,
+                None => continue,
+            },
+            _ => continue,
+        };
+        let end = (i..events.len())
+            .find(|&j| matches!(events[j].0, Event::End(Tag::Paragraph)))
+            .unwrap_or(i);
+        let span = range.start..range.start + text.trim_end().len();
+
+        if attributes.is_empty() {
+            match open.pop() {
+                Some((annotation, open, _)) => containers.push(Container {
+                    annotation,
+                    open,
+                    close: i..end + 1,
+                    children: vec![],
+                }),
+                None => diagnostics.push(
+                    Diagnostic::warning(
+                        diagnostic::UNMATCHED_FENCE,
+                        "`:::` doesn't close any container",
+                        content,
+                        span,
+                    )
+                    .with_fix("remove it, or open a container with `::: {.class}` above it"),
+                ),
+            }
+            continue;
+        }
+
+        let annotation = match attributes.strip_prefix('{') {
+            Some(inner) => annotation::parse(&format!("{{:{}", inner)),
+            None => annotation::parse(&format!("{{:.{}}}", attributes)),
+        };
+        match annotation {
+            Ok(mut annotation) => {
+                annotation.classes = options.resolve_aliases(&annotation.classes);
+                if columns::is_columns(&annotation) {
+                    if let Some(message) = columns::invalid_cols(&annotation) {
+                        diagnostics.push(
+                            Diagnostic::warning(
+                                diagnostic::INVALID_ATTRIBUTE,
+                                message,
+                                content,
+                                span.clone(),
+                            )
+                            .with_fix(format!(
+                                "use a number from 1 to {}, or leave `cols` out to give each block a column",
+                                columns::MAX_COLUMNS
+                            )),
+                        );
+                        annotation.attributes.retain(|(name, _)| name != "cols");
+                    }
+                }
+                open.push((annotation, i..end + 1, span));
+            }
+            Err(e) => {
+                let mut malformed =
+                    Diagnostic::error(diagnostic::MALFORMED_ANNOTATION, e.message, content, span);
+                malformed.fix = e.fix;
+                diagnostics.push(malformed);
+            }
+        }
+    }
+    for (_, _, span) in open {
+        diagnostics.push(
+            Diagnostic::warning(
+                diagnostic::UNMATCHED_FENCE,
+                "this container is never closed",
+                content,
+                span,
+            )
+            .with_fix("close it with a `:::` paragraph, with blank lines around it"),
+        );
+    }
+    containers.sort_by_key(|container| container.open.start);
+
+    for (i, ca) in class_annotations.iter().enumerate() {
+        // Containers nest, so the innermost one around the block is the last to open.
+        let parent = containers
+            .iter_mut()
+            .rev()
+            .find(|container| container.open.end <= ca.start && ca.end < container.close.start);
+        if let Some(parent) = parent {
+            parent.children.push(i);
+        }
+    }
+    (containers, diagnostics)
+}
+
+/// The html replacing a container fence.
+#[derive(Debug)]
+pub(crate) struct Fence {
+    /// The events of the fence's paragraph.
+    pub events: Range<usize>,
+    pub html: String,
+}
+
+/// How containers are rendered: the html for their fences, and for any annotated blocks inside
+/// them that are part of the container, such as tab panels. Numbered blocks' labels go here too.
+#[derive(Debug, Default)]
+pub(crate) struct Layout {
+    /// The fences, in order.
+    pub fences: Vec<Fence>,
+    /// The html to open and close annotated blocks with instead of the wrapper, by index.
+    pub wrappers: BTreeMap<usize, (String, String)>,
+    /// The labels of numbered blocks, such as "Theorem 3.2", by index.
+    pub labels: BTreeMap<usize, String>,
+}
+
+/// Lay out `containers`: most are wrapped like annotated blocks, and some classes get widgets.
+pub(crate) fn layout(
+    containers: &[Container],
+    class_annotations: &[ClassAnnotation],
+    options: &Options,
+) -> Layout {
+    let mut layout = Layout::default();
+    let mut tab_groups = 0;
+    let mut column_groups = 0;
+    for container in containers {
+        let tabs: Vec<usize> = container
+            .children
+            .iter()
+            .copied()
+            .filter(|&i| tabs::is_tab(&class_annotations[i].annotation))
+            .collect();
+        let (open, close) =
+            if container.annotation.classes.iter().any(|c| c == "tabs") && !tabs.is_empty() {
+                tab_groups += 1;
+                let panels: Vec<&Annotation> = tabs
+                    .iter()
+                    .map(|&i| &class_annotations[i].annotation)
+                    .collect();
+                let group = tabs::Group::new(&container.annotation, &panels, tab_groups);
+                for (k, &i) in tabs.iter().enumerate() {
+                    layout.wrappers.insert(
+                        i,
+                        (
+                            group.panel(k, &class_annotations[i].annotation),
+                            "</div>".to_string(),
+                        ),
+                    );
+                }
+                (group.open(tab_groups == 1), "</div>".to_string())
+            } else if columns::is_columns(&container.annotation) {
+                column_groups += 1;
+                (
+                    columns::open(&container.annotation, &options.wrapper, column_groups == 1),
+                    format!("</{}>", options.wrapper),
+                )
+            } else {
+                (
+                    format!(
+                        "<{}{}>",
+                        options.wrapper,
+                        container.annotation.html_attributes()
+                    ),
+                    format!("</{}>", options.wrapper),
+                )
+            };
+        layout.fences.push(Fence {
+            events: container.open.clone(),
+            html: open,
+        });
+        layout.fences.push(Fence {
+            events: container.close.clone(),
+            html: close,
+        });
+    }
+    layout.fences.sort_by_key(|fence| fence.events.start);
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::scan;
+    use mdbook::utils::new_cmark_parser;
+
+    #[test]
+    fn fences() {
+        assert_eq!(fence("::: {.tabs}"), Some("{.tabs}"));
+        assert_eq!(fence(":::: warning ::::"), Some("warning"));
+        assert_eq!(fence(":::"), Some(""));
+        assert_eq!(fence(":: note"), None);
+    }
+
+    #[test]
+    fn nested_containers() {
+        let content = "::: outer\n\n{:.a}\nOne.\n\n::: {.inner #i}\n\n{:.b}\nTwo.\n\n:::\n\n:::\n\n:::\n\n::: open\n";
+        let events: Vec<_> = new_cmark_parser(content, false)
+            .into_offset_iter()
+            .collect();
+        let (class_annotations, _) = scan(content, &events);
+        let (containers, diagnostics) =
+            scan_containers(content, &events, &class_annotations, &Options::default());
+        assert_eq!(containers.len(), 2);
+        assert_eq!(containers[0].annotation.classes, ["outer"]);
+        assert_eq!(containers[0].children, [0]);
+        assert_eq!(containers[1].annotation.id.as_deref(), Some("i"));
+        assert_eq!(containers[1].children, [1]);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].line, 15);
+        assert_eq!(diagnostics[1].message, "this container is never closed");
+    }
+}
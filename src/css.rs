@@ -0,0 +1,164 @@
+use crate::admonition::KINDS;
+use crate::columns;
+use crate::config::Options;
+use crate::inventory::inventory;
+use crate::tabs;
+use mdbook::book::BookItem;
+use mdbook::errors::Error;
+use mdbook::MDBook;
+use std::collections::BTreeSet;
+use std::fs;
+
+/// Collect the class names used in the selectors of a stylesheet.
+///
+/// This is deliberately a scanner rather than a css parser: it only needs to answer
+/// "is `.name` styled anywhere?", so declarations and at-rule preludes are skipped.
+pub fn classes(css: &str) -> BTreeSet<String> {
+    let mut classes = BTreeSet::new();
+    let css = strip_comments(css);
+    let mut prelude_start = 0;
+    for (i, c) in css.char_indices() {
+        match c {
+            '{' => {
+                let prelude = css[prelude_start..i].trim();
+                if !prelude.starts_with('@') {
+                    selector_classes(prelude, &mut classes);
+                }
+                prelude_start = i + 1;
+            }
+            '}' | ';' => prelude_start = i + 1,
+            _ => {}
+        }
+    }
+    classes
+}
+
+/// Collect the classes styled by `<style>` elements in a chapter's raw html.
+pub fn inline_style_classes(html: &str) -> BTreeSet<String> {
+    let mut classes = BTreeSet::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("<style") {
+        let after_tag = match rest[start..].find('>') {
+            Some(end) => &rest[start + end + 1..],
+            None => break,
+        };
+        let end = after_tag.find("</style>").unwrap_or(after_tag.len());
+        classes.extend(self::classes(&after_tag[..end]));
+        rest = &after_tag[end..];
+    }
+    classes
+}
+
+/// Collect the classes a book styles, in `output.html.additional-css` stylesheets and in
+/// `<style>` elements in its chapters, and the classes of the widgets classy styles itself,
+/// including the admonitions when they're turned on. The `exclude-classes` count too, as their
+/// blocks never reach the output.
+pub fn book_classes(md: &MDBook) -> Result<BTreeSet<String>, Error> {
+    let options = Options::from_config(&md.config)?;
+    let mut classes: BTreeSet<String> = tabs::CLASSES
+        .iter()
+        .chain([&columns::CLASS])
+        .map(|c| c.to_string())
+        .collect();
+    if options.admonitions {
+        classes.extend(KINDS.iter().map(|kind| kind.class.to_string()));
+    }
+    classes.extend(options.exclude_classes.iter().cloned());
+    if let Some(html) = md.config.html_config() {
+        for stylesheet in html.additional_css {
+            let css = fs::read_to_string(md.root.join(&stylesheet))
+                .map_err(|e| Error::new(e).context(format!("reading {}", stylesheet.display())))?;
+            classes.extend(self::classes(&css));
+        }
+    }
+    for item in md.book.iter() {
+        if let BookItem::Chapter(chapter) = item {
+            classes.extend(inline_style_classes(&chapter.content));
+        }
+    }
+    Ok(classes)
+}
+
+/// An empty rule for every class the book annotates but doesn't style, and isn't in `defined`
+/// either, with a comment saying where it's first used.
+pub fn stubs(md: &MDBook, defined: &BTreeSet<String>) -> Result<Vec<String>, Error> {
+    let styled = book_classes(md)?;
+    let mut rules = vec![];
+    let options = Options::from_config(&md.config)?;
+    for (class, usage) in inventory(&md.book, &options).classes {
+        if styled.contains(&class) || defined.contains(&class) {
+            continue;
+        }
+        let first = &usage.locations[0];
+        rules.push(format!(
+            "/* {} use{}, first in {}:{} */\n.{} {{\n}}\n",
+            usage.count,
+            if usage.count == 1 { "" } else { "s" },
+            first.chapter_key(),
+            first.line,
+            class
+        ));
+    }
+    Ok(rules)
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+fn selector_classes(selector: &str, classes: &mut BTreeSet<String>) {
+    for (i, _) in selector.match_indices('.') {
+        let name: String = selector[i + 1..]
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        if !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) {
+            classes.insert(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_selector_classes() {
+        let css = r#"
+            /* .commented { color: blue; } */
+            .red{color: red;}
+            div.big > p.little, .padded:hover { padding: 1.5em; }
+            @media (max-width: 600px) { .narrow { width: 100%; } }
+        "#;
+        let found: Vec<_> = classes(css).into_iter().collect();
+        assert_eq!(found, vec!["big", "little", "narrow", "padded", "red"]);
+    }
+
+    #[test]
+    fn finds_inline_style_classes() {
+        let html = "<style>\n    .red{color: red;}\n</style>\n<p>.not-a-class</p>";
+        let found: Vec<_> = inline_style_classes(html).into_iter().collect();
+        assert_eq!(found, vec!["red"]);
+    }
+
+    #[test]
+    fn stubs_unstyled_classes() {
+        let md = MDBook::load("tests/books/lint").unwrap();
+        assert_eq!(
+            stubs(&md, &BTreeSet::new()).unwrap(),
+            vec!["/* 1 use, first in chapter_1.md:6 */\n.missing {\n}\n"]
+        );
+        let defined = BTreeSet::from(["missing".to_string()]);
+        assert!(stubs(&md, &defined).unwrap().is_empty());
+    }
+}
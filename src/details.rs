@@ -0,0 +1,82 @@
+use crate::annotation::{escape, Annotation};
+use pulldown_cmark::{html, Event, Tag};
+use std::ops::Range;
+
+/// The opening html of a collapsible block: a `<details>` element with the annotation's classes,
+/// id and attributes, followed by the `summary` (html). An `open` attribute other than `false`
+/// starts it expanded.
+pub(crate) fn open(annotation: &Annotation, summary: &str) -> String {
+    let mut element = annotation.clone();
+    element
+        .attributes
+        .retain(|(name, _)| name != "summary" && name != "open");
+    let open = match annotation.attribute("open") {
+        Some(value) if value != "false" => " open",
+        _ => "",
+    };
+    format!(
+        "<details{}{}>\n<summary>{}</summary>",
+        element.html_attributes(),
+        open,
+        summary
+    )
+}
+
+/// The summary of a collapsible block: its `summary` attribute, or else the first heading in
+/// `events[range]`, which is returned too so it can be left out of the block.
+pub(crate) fn summary(
+    annotation: &Annotation,
+    events: &[Event],
+    range: Range<usize>,
+) -> (String, Option<Range<usize>>) {
+    if let Some(summary) = annotation.attribute("summary") {
+        return (escape(summary), None);
+    }
+    let start = match (range.start..range.end)
+        .find(|&i| matches!(events[i], Event::Start(Tag::Heading(..))))
+    {
+        Some(start) => start,
+        None => return ("Details".to_string(), None),
+    };
+    let end = (start..range.end)
+        .find(|&i| matches!(events[i], Event::End(Tag::Heading(..))))
+        .unwrap_or(range.end - 1);
+    let mut summary = String::new();
+    html::push_html(&mut summary, events[start + 1..end].iter().cloned());
+    (summary, Some(start..end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation;
+    use pulldown_cmark::HeadingLevel;
+
+    #[test]
+    fn summaries() {
+        let annotation = annotation::parse("{:.collapse #s summary=\"A < B\" open=true}").unwrap();
+        let (summary, heading) = summary(&annotation, &[], 0..0);
+        assert_eq!((summary.as_str(), heading), ("A &lt; B", None));
+        assert_eq!(
+            open(&annotation, &summary),
+            "<details id=\"s\" class=\"collapse\" open>\n<summary>A &lt; B</summary>"
+        );
+
+        let annotation = annotation::parse("{:.collapse open=false}").unwrap();
+        let events = [
+            Event::Start(Tag::BlockQuote),
+            Event::Start(Tag::Heading(HeadingLevel::H3, None, vec![])),
+            Event::Text("The ".into()),
+            Event::Code("answer".into()),
+            Event::End(Tag::Heading(HeadingLevel::H3, None, vec![])),
+            Event::End(Tag::BlockQuote),
+        ];
+        let (summary, heading) = super::summary(&annotation, &events, 0..6);
+        assert_eq!(summary, "The <code>answer</code>");
+        assert_eq!(heading, Some(1..5));
+        assert_eq!(
+            open(&annotation, &summary),
+            "<details class=\"collapse\">\n<summary>The <code>answer</code></summary>"
+        );
+    }
+}
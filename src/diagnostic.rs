@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The rules a [`Diagnostic`] can be reported under, with a short description of each.
+pub const RULES: &[(&str, &str)] = &[
+    (
+        MALFORMED_ANNOTATION,
+        "Annotations must be `{:.class #id key=\"value\"}`.",
+    ),
+    (
+        UNSAFE_ATTRIBUTE,
+        "Event handlers and script urls are not passed through to the html.",
+    ),
+    (
+        DISALLOWED_ATTRIBUTE,
+        "Only the attributes in `allowed-attributes` are passed through to the html.",
+    ),
+    (
+        UNKNOWN_CLASS,
+        "Annotated classes should be styled somewhere.",
+    ),
+    (
+        DETACHED_ANNOTATION,
+        "Annotations apply to the block that starts on the next line.",
+    ),
+    (
+        TRAILING_ANNOTATION,
+        "Annotations go above the paragraph they apply to.",
+    ),
+    (
+        INVALID_ATTRIBUTE,
+        "Some attributes need particular values, such as a number for `cols`.",
+    ),
+    (
+        UNDEFINED_REFERENCE,
+        "`[@id]` refers to a numbered block with that id.",
+    ),
+    (
+        UNMATCHED_FENCE,
+        "Container fences, `::: {.class}` and `:::`, come in pairs.",
+    ),
+    (
+        EMPTY_INDEX,
+        "Indexes and glossaries list the blocks with a class, so some should have it.",
+    ),
+    (
+        INVALID_FRONT_MATTER,
+        "Front matter is TOML between `+++` lines, setting `classes`, `classy`, `aliases` or `strict`. YAML isn't supported.",
+    ),
+];
+
+pub const MALFORMED_ANNOTATION: &str = "malformed-annotation";
+pub const UNSAFE_ATTRIBUTE: &str = "unsafe-attribute";
+pub const DISALLOWED_ATTRIBUTE: &str = "disallowed-attribute";
+pub const UNKNOWN_CLASS: &str = "unknown-class";
+pub const DETACHED_ANNOTATION: &str = "detached-annotation";
+pub const TRAILING_ANNOTATION: &str = "trailing-annotation";
+pub const INVALID_ATTRIBUTE: &str = "invalid-attribute";
+pub const UNDEFINED_REFERENCE: &str = "undefined-reference";
+pub const UNMATCHED_FENCE: &str = "unmatched-fence";
+pub const EMPTY_INDEX: &str = "empty-index";
+pub const INVALID_FRONT_MATTER: &str = "invalid-front-matter";
+
+/// How serious a [`Diagnostic`] is.
+/// Errors make `mdbook-classy check` exit non-zero, warnings do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => f.write_str("warning"),
+            Severity::Error => f.write_str("error"),
+        }
+    }
+}
+
+/// A problem found in a chapter's annotations, located by 1-based lines and columns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<String>,
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
+
+impl Diagnostic {
+    /// Build a diagnostic covering the byte range `span` of `content`.
+    pub fn new(
+        severity: Severity,
+        rule: &'static str,
+        message: impl Into<String>,
+        content: &str,
+        span: Range<usize>,
+    ) -> Self {
+        let (line, column) = line_col(content, span.start);
+        let (end_line, end_column) = line_col(content, span.end);
+        Diagnostic {
+            rule,
+            severity,
+            message: message.into(),
+            fix: None,
+            path: None,
+            line,
+            column,
+            end_line,
+            end_column,
+        }
+    }
+
+    pub fn error(
+        rule: &'static str,
+        message: impl Into<String>,
+        content: &str,
+        span: Range<usize>,
+    ) -> Self {
+        Diagnostic::new(Severity::Error, rule, message, content, span)
+    }
+
+    pub fn warning(
+        rule: &'static str,
+        message: impl Into<String>,
+        content: &str,
+        span: Range<usize>,
+    ) -> Self {
+        Diagnostic::new(Severity::Warning, rule, message, content, span)
+    }
+
+    pub fn with_fix(mut self, fix: impl Into<String>) -> Self {
+        self.fix = Some(fix.into());
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "rule": self.rule,
+            "severity": self.severity.to_string(),
+            "path": self.path.as_ref().map(|path| uri(path)),
+            "line": self.line,
+            "column": self.column,
+            "end_line": self.end_line,
+            "end_column": self.end_column,
+            "message": self.message,
+            "fix": self.fix,
+        })
+    }
+
+    fn to_sarif(&self) -> Value {
+        let mut message = self.message.clone();
+        if let Some(fix) = &self.fix {
+            message.push_str(&format!("\nSuggested fix: {}", fix));
+        }
+        let level = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        let mut result = json!({
+            "ruleId": self.rule,
+            "level": level,
+            "message": { "text": message },
+        });
+        // A SARIF location needs a file, so diagnostics from stdin have none.
+        if let Some(path) = &self.path {
+            result["locations"] = json!([{
+                "physicalLocation": {
+                    "artifactLocation": { "uri": uri(path) },
+                    "region": {
+                        "startLine": self.line,
+                        "startColumn": self.column,
+                        "endLine": self.end_line,
+                        "endColumn": self.end_column,
+                    }
+                }
+            }]);
+        }
+        if let Some(fix) = &self.fix {
+            result["properties"] = json!({ "fix": fix });
+        }
+        result
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        write!(
+            f,
+            "{}:{}: {}[{}]: {}",
+            self.line, self.column, self.severity, self.rule, self.message
+        )?;
+        if let Some(fix) = &self.fix {
+            write!(f, " (fix: {})", fix)?;
+        }
+        Ok(())
+    }
+}
+
+/// How to print diagnostics: one per line for people, JSON lines or SARIF 2.1 for tools.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Sarif,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            _ => Err(format!(
+                "unknown diagnostics format `{}`, expected text, json or sarif",
+                s
+            )),
+        }
+    }
+}
+
+/// Write `diagnostics` to `out` in the given format.
+pub fn write(out: &mut impl Write, diagnostics: &[Diagnostic], format: Format) -> io::Result<()> {
+    match format {
+        Format::Text => {
+            for diagnostic in diagnostics {
+                writeln!(out, "{}", diagnostic)?;
+            }
+        }
+        Format::Json => {
+            for diagnostic in diagnostics {
+                writeln!(out, "{}", diagnostic.to_json())?;
+            }
+        }
+        Format::Sarif => {
+            serde_json::to_writer_pretty(&mut *out, &sarif(diagnostics))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Build a SARIF 2.1.0 log with a single run containing `diagnostics`.
+pub fn sarif(diagnostics: &[Diagnostic]) -> Value {
+    let rules: Vec<Value> = RULES
+        .iter()
+        .map(|(id, description)| json!({ "id": id, "shortDescription": { "text": description } }))
+        .collect();
+    let results: Vec<Value> = diagnostics.iter().map(Diagnostic::to_sarif).collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// Paths are reported with forward slashes so SARIF consumers can resolve them on any platform.
+fn uri(path: &std::path::Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Convert a byte offset into a 1-based (line, column) pair, counting columns in characters.
+pub fn line_col(content: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(content.len());
+    let before = &content[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unclosed() -> Diagnostic {
+        Diagnostic::error(MALFORMED_ANNOTATION, "unclosed annotation", "a\n{:.b", 2..6)
+            .with_fix("add a closing `}`")
+            .with_path("src/ch.md")
+    }
+
+    #[test]
+    fn line_col_counts_from_one() {
+        let content = "first\nsecond\n{:.red}";
+        assert_eq!(line_col(content, 0), (1, 1));
+        assert_eq!(line_col(content, 6), (2, 1));
+        assert_eq!(line_col(content, 16), (3, 4));
+    }
+
+    #[test]
+    fn display_includes_path_rule_and_fix() {
+        assert_eq!(
+            unclosed().to_string(),
+            "src/ch.md:2:1: error[malformed-annotation]: unclosed annotation (fix: add a closing `}`)"
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let mut out = vec![];
+        write(&mut out, &[unclosed(), unclosed()], Format::Json).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 2);
+        let value: Value = serde_json::from_str(out.lines().next().unwrap()).unwrap();
+        assert_eq!(value["rule"], "malformed-annotation");
+        assert_eq!(value["path"], "src/ch.md");
+        assert_eq!(
+            (value["end_line"].as_u64(), value["end_column"].as_u64()),
+            (Some(2), Some(5))
+        );
+        assert_eq!(value["fix"], "add a closing `}`");
+    }
+
+    #[test]
+    fn sarif_log() {
+        let log = sarif(&[unclosed()]);
+        assert_eq!(log["version"], "2.1.0");
+        let result = &log["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "malformed-annotation");
+        assert_eq!(result["level"], "error");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/ch.md");
+        assert_eq!(location["region"]["startLine"], 2);
+
+        let stdin = Diagnostic {
+            path: None,
+            ..unclosed()
+        };
+        let log = sarif(&[stdin]);
+        let result = &log["runs"][0]["results"][0];
+        assert!(result.get("locations").is_none());
+        assert_eq!(result["properties"]["fix"], "add a closing `}`");
+
+        let no_fix = Diagnostic {
+            fix: None,
+            ..unclosed()
+        };
+        let log = sarif(&[no_fix]);
+        assert!(log["runs"][0]["results"][0].get("properties").is_none());
+    }
+}
@@ -0,0 +1,63 @@
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::handler::HandlerError;
+use std::error::Error;
+use std::fmt;
+
+/// Why [`transform`](crate::transform) failed.
+///
+/// Problems with annotations are not errors: they are reported as
+/// [`Diagnostic`](crate::diagnostic::Diagnostic)s and the annotation is left in place.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ClassyError {
+    /// In strict mode, annotations had errors. The diagnostics include any warnings too.
+    Strict(Vec<Diagnostic>),
+    /// A [`ClassHandler`](crate::handler::ClassHandler) failed.
+    Handler { class: String, source: HandlerError },
+    /// An option has a value that can't be used.
+    InvalidOption(String),
+    /// The transformed events couldn't be written back out as markdown.
+    Render(fmt::Error),
+}
+
+impl fmt::Display for ClassyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClassyError::Strict(diagnostics) => {
+                let errors: Vec<_> = diagnostics
+                    .iter()
+                    .filter(|d| d.severity == Severity::Error)
+                    .collect();
+                write!(f, "{} annotation error", errors.len())?;
+                if errors.len() != 1 {
+                    f.write_str("s")?;
+                }
+                for error in errors {
+                    write!(f, "\n{}", error)?;
+                }
+                Ok(())
+            }
+            ClassyError::Handler { class, source } => {
+                write!(f, "the handler for `.{}` failed: {}", class, source)
+            }
+            ClassyError::InvalidOption(message) => write!(f, "invalid option: {}", message),
+            ClassyError::Render(_) => f.write_str("failed to render the transformed markdown"),
+        }
+    }
+}
+
+impl Error for ClassyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClassyError::Handler { source, .. } => Some(source.as_ref()),
+            ClassyError::Strict(_) | ClassyError::InvalidOption(_) => None,
+            ClassyError::Render(e) => Some(e),
+        }
+    }
+}
+
+impl From<fmt::Error> for ClassyError {
+    fn from(e: fmt::Error) -> Self {
+        ClassyError::Render(e)
+    }
+}
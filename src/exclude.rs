@@ -0,0 +1,73 @@
+use crate::config::Options;
+use crate::container::scan_containers;
+use crate::preprocessor::scan_with_options;
+use mdbook::utils::new_cmark_parser;
+use std::ops::Range;
+
+/// `content` without the annotated blocks and containers that have one of the
+/// `exclude-classes`. Only their line breaks are kept, so diagnostics still point at the right
+/// lines.
+pub(crate) fn exclude(content: &str, options: &Options) -> String {
+    if options.exclude_classes.is_empty() {
+        return content.to_string();
+    }
+    let events: Vec<_> = new_cmark_parser(content, false)
+        .into_offset_iter()
+        .collect();
+    let (class_annotations, _) = scan_with_options(content, &events, options);
+    let (containers, _) = scan_containers(content, &events, &class_annotations, options);
+    let excluded = |classes: &[String]| {
+        classes
+            .iter()
+            .any(|class| options.exclude_classes.contains(class))
+    };
+    let mut ranges: Vec<Range<usize>> = class_annotations
+        .iter()
+        .filter(|ca| excluded(&ca.annotation.classes))
+        .map(|ca| events[ca.start].1.start..events[ca.end].1.end)
+        .chain(
+            containers
+                .iter()
+                .filter(|container| excluded(&container.annotation.classes))
+                .map(|container| {
+                    events[container.open.start].1.start..events[container.close.end - 1].1.end
+                }),
+        )
+        .collect();
+    ranges.sort_by_key(|range| range.start);
+
+    let mut kept = String::with_capacity(content.len());
+    let mut last = 0;
+    for range in ranges {
+        // Blocks inside an excluded container are already gone.
+        if range.end <= last {
+            continue;
+        }
+        let start = range.start.max(last);
+        kept.push_str(&content[last..start]);
+        kept.extend(content[start..range.end].matches('\n'));
+        last = range.end;
+    }
+    kept.push_str(&content[last..]);
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excludes_blocks_and_containers() {
+        let options = Options {
+            exclude_classes: vec!["internal".to_string()],
+            aliases: [("secret".to_string(), "internal".to_string())].into(),
+            ..Options::default()
+        };
+        let content = "Public.\n\n{:.internal}\nHidden\ntext.\n\n::: {.note .internal}\n\n{:.tip}\nAlso hidden.\n\n:::\n\n{:.secret}\n- a\n- b\n\n{:.note}\nShown.\n";
+        assert_eq!(
+            exclude(content, &options),
+            "Public.\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n{:.note}\nShown.\n"
+        );
+        assert_eq!(exclude(content, &Options::default()), content);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::config::{Options, Strategy};
+use crate::diagnostic::line_col;
+use crate::error::ClassyError;
+use crate::exclude::exclude;
+use crate::preprocessor::{trace_chapter, BlockKind, Output, Splice};
+use mdbook::utils::new_cmark_parser;
+use pulldown_cmark::Event;
+use serde::Serialize;
+use std::fmt;
+use std::ops::Range;
+
+/// Everything classy sees and does while transforming a piece of markdown: the parser's events,
+/// the annotations found in them, how the events are spliced back together, and the result.
+#[derive(Debug, Serialize)]
+pub struct Explanation {
+    pub events: Vec<EventRecord>,
+    pub annotations: Vec<AnnotationRecord>,
+    pub plan: Vec<Splice>,
+    pub diagnostics: Vec<String>,
+    pub output: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventRecord {
+    pub event: String,
+    /// Byte range of the event in the markdown classy parses: the input without its front
+    /// matter or excluded blocks, which keep their line breaks.
+    pub source: Range<usize>,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// An annotation and the events (by index) of the block it was applied to.
+#[derive(Debug, Serialize)]
+pub struct AnnotationRecord {
+    pub annotation: String,
+    pub line: usize,
+    pub column: usize,
+    pub block: BlockKind,
+    /// The paragraph event holding the annotation.
+    pub start: usize,
+    /// The first event of the annotated content.
+    pub body_start: usize,
+    /// The event ending the annotated block.
+    pub end: usize,
+}
+
+/// Explain how classy transforms `content`.
+pub fn explain(content: &str, options: &Options) -> Result<Explanation, ClassyError> {
+    let (
+        Output {
+            content: output,
+            diagnostics,
+        },
+        trace,
+    ) = trace_chapter(
+        &exclude(content, options),
+        None,
+        None,
+        options,
+        Strategy::Wrap,
+    )?;
+    let content = trace.content.as_str();
+    let incoming: Vec<(Event, Range<usize>)> = new_cmark_parser(content, false)
+        .into_offset_iter()
+        .collect();
+
+    let annotations = trace
+        .class_annotations
+        .iter()
+        .map(|ca| {
+            let (line, column) = line_col(content, ca.span.start);
+            AnnotationRecord {
+                annotation: ca.annotation.to_string(),
+                line,
+                column,
+                block: ca.block,
+                start: ca.start,
+                body_start: ca.body_start,
+                end: ca.end,
+            }
+        })
+        .collect();
+    let events = incoming
+        .iter()
+        .map(|(event, source)| {
+            let (line, column) = line_col(content, source.start);
+            EventRecord {
+                event: describe(event),
+                source: source.clone(),
+                line,
+                column,
+            }
+        })
+        .collect();
+
+    Ok(Explanation {
+        events,
+        annotations,
+        plan: trace.plan,
+        diagnostics: diagnostics.iter().map(ToString::to_string).collect(),
+        output,
+    })
+}
+
+/// Like the event's `Debug` output, without the `Borrowed`/`Boxed` wrapping of its text.
+fn describe(event: &Event) -> String {
+    match event {
+        Event::Text(text) => format!("Text({:?})", text.as_ref()),
+        Event::Code(text) => format!("Code({:?})", text.as_ref()),
+        Event::Html(text) => format!("Html({:?})", text.as_ref()),
+        Event::FootnoteReference(text) => format!("FootnoteReference({:?})", text.as_ref()),
+        event => format!("{:?}", event),
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "events:")?;
+        for (i, record) in self.events.iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>3}  {:>3}:{:<3}  {}",
+                i, record.line, record.column, record.event
+            )?;
+        }
+
+        writeln!(f, "\nannotations:")?;
+        if self.annotations.is_empty() {
+            writeln!(f, "  (none)")?;
+        }
+        for record in &self.annotations {
+            writeln!(
+                f,
+                "  {}:{}  {} applies to the {} in events {}..={}, content from event {}",
+                record.line,
+                record.column,
+                record.annotation,
+                record.block,
+                record.start,
+                record.end,
+                record.body_start
+            )?;
+        }
+
+        writeln!(f, "\nplan:")?;
+        for splice in &self.plan {
+            match splice {
+                Splice::Copy { events } => {
+                    writeln!(f, "  copy events {}..={}", events.start, events.end - 1)?
+                }
+                Splice::Html { html } => writeln!(f, "  insert {}", html)?,
+                Splice::BlankLine => writeln!(f, "  insert blank line")?,
+            }
+        }
+
+        if !self.diagnostics.is_empty() {
+            writeln!(f, "\ndiagnostics:")?;
+            for diagnostic in &self.diagnostics {
+                writeln!(f, "  {}", diagnostic)?;
+            }
+        }
+
+        writeln!(f, "\noutput:")?;
+        writeln!(f, "{}", self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_a_transform() {
+        let explanation = explain("{:.note}\nText.\n", &Options::default()).unwrap();
+        assert_eq!(explanation.events[1].event, "Text(\"{:.note}\")");
+        assert_eq!(explanation.annotations[0].annotation, "{:.note}");
+        assert_eq!(explanation.annotations[0].body_start, 3);
+        assert_eq!(
+            explanation.to_string(),
+            "events:
+    0    1:1    Start(Paragraph)
+    1    1:1    Text(\"{:.note}\")
+    2    1:9    SoftBreak
+    3    2:1    Text(\"Text.\")
+    4    1:1    End(Paragraph)
+
+annotations:
+  1:1  {:.note} applies to the paragraph in events 0..=4, content from event 3
+
+plan:
+  insert <div class=\"note\">
+  insert blank line
+  copy events 0..=0
+  copy events 3..=4
+  insert </div>
+
+output:
+<div class=\"note\">
+
+Text.
+
+</div>
+"
+        );
+    }
+
+    #[test]
+    fn explains_the_chapter_as_transformed() {
+        let explanation = explain(
+            "+++\nclasses = \"appendix\"\n+++\n{:.note}\nText.\n",
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(explanation.events[1].line, 4);
+        assert_eq!(explanation.annotations[0].line, 4);
+        assert_eq!(
+            explanation.plan[..3],
+            [
+                Splice::Html {
+                    html: "<div class=\"appendix\">".to_string()
+                },
+                Splice::BlankLine,
+                Splice::Html {
+                    html: "<div class=\"note\">".to_string()
+                },
+            ]
+        );
+        assert!(explanation.output.starts_with("<div class=\"appendix\">"));
+    }
+
+    #[test]
+    fn json_plan() {
+        let explanation = explain("- item\n", &Options::default()).unwrap();
+        let json = serde_json::to_value(&explanation).unwrap();
+        assert_eq!(
+            json["plan"][0],
+            serde_json::json!({"step": "copy", "events": {"start": 0, "end": 5}})
+        );
+        assert_eq!(json["annotations"], serde_json::json!([]));
+    }
+}
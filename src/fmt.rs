@@ -0,0 +1,153 @@
+use crate::annotation;
+use crate::preprocessor::{detached_gap, is_off, off_ranges, scan, trailing_annotation, BlockKind};
+use mdbook::book::BookItem;
+use mdbook::errors::Error;
+use mdbook::utils::new_cmark_parser;
+use mdbook::MDBook;
+use pulldown_cmark::{Event, Tag};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Rewrite the annotations in `content` into the canonical `{:.class #id key="value"}` form.
+///
+/// Annotations separated from their paragraph by a blank line are reattached, and trailing
+/// annotations are moved above their paragraph. Only the annotations and the line breaks around
+/// them change: the rest of the text never goes through the (lossy) markdown renderer.
+pub fn format(content: &str) -> String {
+    let events: Vec<_> = new_cmark_parser(content, false)
+        .into_offset_iter()
+        .collect();
+    let (class_annotations, _) = scan(content, &events);
+
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    for ca in &class_annotations {
+        let canonical = ca.annotation.to_string();
+        if content[ca.span.clone()] != canonical && ca.annotation.round_trips() {
+            edits.push((ca.span.clone(), canonical));
+        }
+        if ca.block == BlockKind::Paragraph && ca.body_start == ca.end {
+            if let Some(gap) = detached_gap(content, &events, ca.span.end, ca.end) {
+                edits.push((gap, "\n".to_string()));
+            }
+        }
+    }
+
+    // Annotations between `<!-- classy:off -->` and `<!-- classy:on -->` are left as written.
+    let off = off_ranges(&events);
+    for (event, range) in &events {
+        if !matches!(event, Event::Start(Tag::Paragraph)) || is_off(&off, range.start) {
+            continue;
+        }
+        let first_line = content[range.clone()].lines().next().unwrap_or("");
+        if annotation::is_annotation(first_line) {
+            continue;
+        }
+        if let Some(span) = trailing_annotation(content, range.clone()) {
+            let trailing =
+                annotation::parse(&content[span.clone()]).expect("trailing annotations parse");
+            if !trailing.round_trips() {
+                continue;
+            }
+            let canonical = trailing.to_string();
+            // Keep any blockquote marker or indentation for the line the annotation moves to.
+            let line_break = content[..span.start].rfind('\n').unwrap_or(range.start);
+            let prefix = &content[line_break + 1..span.start];
+            edits.push((
+                range.start..range.start,
+                format!("{}\n{}", canonical, prefix),
+            ));
+            edits.push((line_break..span.end, String::new()));
+        }
+    }
+
+    edits.sort_by_key(|(range, _)| range.start);
+    let mut formatted = content.to_string();
+    for (range, replacement) in edits.into_iter().rev() {
+        formatted.replace_range(range, &replacement);
+    }
+    formatted
+}
+
+/// Format every chapter of the book at `root`, returning the files that changed.
+/// With `check`, nothing is written and the returned files are the ones that would change.
+pub fn format_book(root: &Path, check: bool) -> Result<Vec<PathBuf>, Error> {
+    let md = MDBook::load(root)?;
+    let src_dir = md.root.join(&md.config.book.src);
+
+    let mut changed = vec![];
+    for item in md.book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(chapter) => chapter,
+            _ => continue,
+        };
+        let path = match &chapter.source_path {
+            Some(path) => src_dir.join(path),
+            None => continue,
+        };
+        let formatted = format(&chapter.content);
+        if formatted != chapter.content {
+            if !check {
+                fs::write(&path, formatted)?;
+            }
+            changed.push(path);
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalizes_annotation_syntax() {
+        let content =
+            "# Title\n\n{: .a b #top}\nText   with *odd*  spacing.\n\n```md\n{:.a b}\n```\n";
+        let expected =
+            "# Title\n\n{:.a .b #top}\nText   with *odd*  spacing.\n\n```md\n{:.a b}\n```\n";
+        assert_eq!(format(content), expected);
+    }
+
+    #[test]
+    fn reattaches_detached_annotations() {
+        let content = "{:.a}\n\n\nText.\n\n> {:.b}\n>\n> Quote.\n";
+        let expected = "{:.a}\nText.\n\n> {:.b}\n> Quote.\n";
+        assert_eq!(format(content), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn moves_trailing_annotations() {
+        let content = "First line\nsecond line\n{: .a}\n\n> Quoted\n> {:.b}\n";
+        let expected = "{:.a}\nFirst line\nsecond line\n\n> {:.b}\n> Quoted\n";
+        assert_eq!(format(content), expected);
+        assert_eq!(format(expected), expected);
+    }
+
+    #[test]
+    fn leaves_classy_off_alone() {
+        let content = "<!-- classy:off -->\n\ntext\n{: .a}\n\n{: .b}\nText.\n\n\
+                       <!-- classy:on -->\n\ntext\n{: .c}\n";
+        let expected = "<!-- classy:off -->\n\ntext\n{: .a}\n\n{: .b}\nText.\n\n\
+                        <!-- classy:on -->\n\n{:.c}\ntext\n";
+        assert_eq!(format(content), expected);
+    }
+
+    #[test]
+    fn keeps_values_with_both_quotes_parseable() {
+        let content = "{: .a data-x=it's\"odd\"}\nText.\n";
+        let formatted = format(content);
+        assert_eq!(formatted, "{:.a data-x=it's\"odd\"}\nText.\n");
+        let events: Vec<_> = new_cmark_parser(&formatted, false)
+            .into_offset_iter()
+            .collect();
+        assert_eq!(scan(&formatted, &events).1, vec![]);
+    }
+
+    #[test]
+    fn leaves_malformed_annotations_alone() {
+        let content = "{:.a\nText.\n";
+        assert_eq!(format(content), content);
+    }
+}
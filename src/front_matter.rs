@@ -0,0 +1,169 @@
+use crate::config::Options;
+use crate::diagnostic::{self, Diagnostic};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Settings for one chapter, from a TOML block between `+++` lines at the top of it:
+///
+/// ```markdown
+/// +++
+/// classes = "appendix wide"
+/// strict = true
+///
+/// [aliases]
+/// tip = "callout callout-tip"
+/// +++
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct FrontMatter {
+    /// Classes for the whole chapter, as if from `{::chapter}`.
+    pub classes: String,
+    /// Leave the chapter's annotations alone when `false`.
+    pub classy: bool,
+    /// Aliases for this chapter, on top of the book's.
+    pub aliases: BTreeMap<String, String>,
+    /// Overrides the book's `strict` for this chapter.
+    pub strict: Option<bool>,
+}
+
+impl Default for FrontMatter {
+    fn default() -> Self {
+        FrontMatter {
+            classes: String::new(),
+            classy: true,
+            aliases: BTreeMap::new(),
+            strict: None,
+        }
+    }
+}
+
+impl FrontMatter {
+    /// The book's `options`, with this chapter's settings on top.
+    pub fn apply(&self, options: &Options) -> Options {
+        let mut options = options.clone();
+        options.aliases.extend(self.aliases.clone());
+        if let Some(strict) = self.strict {
+            options.strict = strict;
+        }
+        options
+    }
+
+    /// The chapter's classes.
+    pub fn classes(&self) -> Vec<String> {
+        self.classes.split_whitespace().map(String::from).collect()
+    }
+}
+
+/// The byte range of a block between `fence` lines at the top of `content`, and what's in it.
+fn find<'a>(content: &'a str, fence: &str) -> Option<(Range<usize>, &'a str)> {
+    let mut lines = content.split_inclusive('\n');
+    let first = lines.next()?;
+    if first.trim_end() != fence {
+        return None;
+    }
+    let mut end = first.len();
+    for line in lines {
+        if line.trim_end() == fence {
+            return Some((0..end + line.len(), &content[first.len()..end]));
+        }
+        end += line.len();
+    }
+    None
+}
+
+/// Whether `content` starts with what looks like YAML front matter: `key: value` lines between
+/// `---` lines. A `---` on its own could be a thematic break, so the line after it has to look
+/// like a key too.
+fn is_yaml(content: &str) -> bool {
+    let (_, yaml) = match find(content, "---") {
+        Some(found) => found,
+        None => return false,
+    };
+    yaml.lines()
+        .next()
+        .and_then(|line| line.split_once(':'))
+        .is_some_and(|(key, _)| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+/// A chapter's front matter, with `content` as it is without it. Only the line breaks of the
+/// block are kept, so diagnostics still point at the right lines. Front matter that can't be
+/// read is reported, and the chapter gets the book's settings. YAML front matter isn't read, and
+/// is left in the chapter with a warning.
+pub(crate) fn split(content: &str) -> (String, Option<FrontMatter>, Vec<Diagnostic>) {
+    let (range, toml) = match find(content, "+++") {
+        Some(found) => found,
+        None if is_yaml(content) => {
+            let diagnostic = Diagnostic::warning(
+                diagnostic::INVALID_FRONT_MATTER,
+                "YAML front matter isn't supported, so it's left in the chapter",
+                content,
+                0..3,
+            )
+            .with_fix("write the front matter as TOML between `+++` lines");
+            return (content.to_string(), None, vec![diagnostic]);
+        }
+        None => return (content.to_string(), None, vec![]),
+    };
+    let mut diagnostics = vec![];
+    let front_matter = match toml::from_str(toml) {
+        Ok(front_matter) => Some(front_matter),
+        Err(e) => {
+            diagnostics.push(
+                Diagnostic::error(
+                    diagnostic::INVALID_FRONT_MATTER,
+                    format!("the front matter can't be read: {}", e),
+                    content,
+                    0..3,
+                )
+                .with_fix("front matter sets `classes`, `classy`, `aliases` or `strict`"),
+            );
+            None
+        }
+    };
+    let mut stripped: String = content[range.clone()].matches('\n').collect();
+    stripped.push_str(&content[range.end..]);
+    (stripped, front_matter, diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_front_matter() {
+        let content = "+++\nclasses = \"appendix\"\nclassy = false\n\n[aliases]\ntip = \"note\"\n+++\n# Title\n";
+        let (stripped, front_matter, diagnostics) = split(content);
+        assert_eq!(stripped, "\n\n\n\n\n\n\n# Title\n");
+        let front_matter = front_matter.unwrap();
+        assert_eq!(front_matter.classes(), ["appendix"]);
+        assert!(!front_matter.classy);
+        assert_eq!(
+            front_matter.apply(&Options::default()).aliases["tip"],
+            "note"
+        );
+        assert!(diagnostics.is_empty());
+
+        let (stripped, front_matter, diagnostics) = split("+++\ncolour = \"red\"\n+++\ntext");
+        assert_eq!(stripped, "\n\n\ntext");
+        assert_eq!(front_matter, None);
+        assert_eq!(diagnostics[0].rule, diagnostic::INVALID_FRONT_MATTER);
+
+        assert_eq!(split("+++\nnot closed\n").1, None);
+        assert_eq!(split("text\n+++\n").0, "text\n+++\n");
+
+        let (stripped, front_matter, diagnostics) = split("---\nclasses: appendix\n---\ntext");
+        assert_eq!(stripped, "---\nclasses: appendix\n---\ntext");
+        assert_eq!(front_matter, None);
+        assert_eq!(diagnostics[0].rule, diagnostic::INVALID_FRONT_MATTER);
+        assert_eq!(diagnostics[0].severity, diagnostic::Severity::Warning);
+        // A thematic break, then a setext heading.
+        assert!(split("---\n\nSee: this\n---\n").2.is_empty());
+    }
+}
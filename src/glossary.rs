@@ -0,0 +1,200 @@
+use crate::handler::ChapterInfo;
+use crate::index::{self, link};
+use crate::numbering::{link_text, link_to};
+use crate::preprocessor::ClassAnnotation;
+use mdbook::utils::normalize_id;
+use pulldown_cmark::{CowStr, Event, Tag};
+use std::collections::BTreeSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The class of the blocks that define glossary terms.
+const TERM: &str = "term";
+
+fn is_term(ca: &ClassAnnotation) -> bool {
+    ca.annotation.classes.iter().any(|class| class == TERM)
+}
+
+/// The paragraphs in `events` that are `{{#classy-glossary}}` directives.
+pub(crate) fn directives<'e, 'a: 'e, E>(
+    events: &'e [E],
+    event: impl Fn(&'e E) -> &'e Event<'a>,
+) -> Vec<(Range<usize>, ())> {
+    index::paragraphs(events, event, |text| {
+        let inner = text.trim().strip_prefix("{{")?.strip_suffix("}}")?;
+        (inner.trim() == "#classy-glossary").then_some(())
+    })
+}
+
+/// The term a block defines: its `name`, or else what an index would call it.
+fn name(ca: &ClassAnnotation, events: &[(Event, Range<usize>)]) -> String {
+    match ca.annotation.attribute("name") {
+        Some(name) => name.to_string(),
+        None => index::title(ca, events),
+    }
+}
+
+/// The text of a term's definition, on one line, after the term itself when that's the first
+/// line.
+fn definition(ca: &ClassAnnotation, events: &[(Event, Range<usize>)]) -> String {
+    let mut text = String::new();
+    for (event, _) in &events[ca.body_start..ca.end] {
+        match event {
+            Event::Text(part) | Event::Code(part) => text.push_str(part),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) => text.push(' '),
+            _ => {}
+        }
+    }
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let named =
+        ca.annotation.attribute("name").is_some() || ca.annotation.attribute("title").is_some();
+    match text.strip_prefix(&index::title(ca, events)) {
+        Some(rest) if !named => rest.trim_start().to_string(),
+        _ => text,
+    }
+}
+
+/// Give the terms among `class_annotations` without an id one made from their name, like
+/// `term-idempotent`.
+pub(crate) fn assign_ids(
+    events: &[(Event, Range<usize>)],
+    class_annotations: &mut [ClassAnnotation],
+) {
+    for ca in class_annotations {
+        if is_term(ca) && ca.annotation.id.is_none() {
+            ca.annotation.id = Some(format!("{}-{}", TERM, normalize_id(&name(ca, events))));
+        }
+    }
+}
+
+/// A term defined by a `{:.term}` block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Term {
+    pub name: String,
+    pub definition: String,
+    pub id: String,
+    pub chapter: String,
+    pub path: Option<PathBuf>,
+}
+
+/// The terms defined across a book.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Glossary {
+    pub terms: Vec<Term>,
+}
+
+impl Glossary {
+    /// Add the terms among `class_annotations`, which `assign_ids` has given ids, in `chapter`.
+    pub fn add(
+        &mut self,
+        events: &[(Event, Range<usize>)],
+        class_annotations: &[ClassAnnotation],
+        chapter: &ChapterInfo,
+    ) {
+        for ca in class_annotations.iter().filter(|ca| is_term(ca)) {
+            if let Some(id) = &ca.annotation.id {
+                self.terms.push(Term {
+                    name: name(ca, events),
+                    definition: definition(ca, events),
+                    id: id.clone(),
+                    chapter: chapter.name.clone(),
+                    path: chapter.path.clone(),
+                });
+            }
+        }
+    }
+
+    /// The terms in alphabetical order, each with its definition and a link back to it from the
+    /// chapter at `from`.
+    pub fn list(&self, from: Option<&Path>) -> Vec<Event<'static>> {
+        if self.terms.is_empty() {
+            return vec![];
+        }
+        let mut terms: Vec<&Term> = self.terms.iter().collect();
+        terms.sort_by_cached_key(|term| (term.name.to_lowercase(), term.name.clone()));
+        let mut events = vec![Event::Start(Tag::List(None))];
+        for term in terms {
+            events.extend([
+                Event::Start(Tag::Item),
+                Event::Start(Tag::Strong),
+                Event::Text(CowStr::from(term.name.clone())),
+                Event::End(Tag::Strong),
+            ]);
+            let mut text = String::new();
+            if !term.definition.is_empty() {
+                text = format!(": {}", term.definition);
+            }
+            events.push(Event::Text(CowStr::from(format!("{} (", text))));
+            let (label, url) = match &term.path {
+                Some(path) => (term.chapter.as_str(), link_to(from, path, Some(&term.id))),
+                None => ("↩", format!("#{}", term.id)),
+            };
+            link(&mut events, label, url);
+            events.extend([Event::Text(CowStr::from(")")), Event::End(Tag::Item)]);
+        }
+        events.push(Event::End(Tag::List(None)));
+        events
+    }
+
+    /// Link the first use of each term in the chapter at `from` to its definition, unless it's
+    /// defined in that chapter. Uses match whole words, ignoring case.
+    pub fn link_terms<'a>(&self, events: Vec<Event<'a>>, from: Option<&Path>) -> Vec<Event<'a>> {
+        let mut linked = BTreeSet::new();
+        link_text(events, |text| {
+            let lower = text.to_ascii_lowercase();
+            let mut found: Vec<(Range<usize>, &Term)> = vec![];
+            for term in &self.terms {
+                let name = term.name.to_ascii_lowercase();
+                if term.path.as_deref() == from || linked.contains(&name) {
+                    continue;
+                }
+                if let Some(at) = find_word(&lower, &name) {
+                    found.push((at..at + name.len(), term));
+                }
+            }
+            found.sort_by_key(|(range, _)| range.start);
+            let mut links = vec![];
+            let mut last = 0;
+            for (range, term) in found {
+                if range.start < last || !linked.insert(term.name.to_ascii_lowercase()) {
+                    continue;
+                }
+                last = range.end;
+                let url = match &term.path {
+                    Some(path) => link_to(from, path, Some(&term.id)),
+                    None => format!("#{}", term.id),
+                };
+                links.push((range.clone(), text[range].to_string(), url));
+            }
+            links
+        })
+    }
+}
+
+/// Where `word` first appears in `text` on its own, rather than as part of a longer word.
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    if word.is_empty() {
+        return None;
+    }
+    text.match_indices(word).map(|(at, _)| at).find(|&at| {
+        let before = text[..at].chars().next_back();
+        let after = text[at + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_words() {
+        assert_eq!(find_word("an idempotent call", "idempotent"), Some(3));
+        assert_eq!(find_word("non-idempotent", "idempotent"), Some(4));
+        assert_eq!(
+            find_word("idempotently, idempotent.", "idempotent"),
+            Some(14)
+        );
+        assert_eq!(find_word("unidempotent", "idempotent"), None);
+    }
+}
@@ -0,0 +1,280 @@
+use crate::annotation::Annotation;
+use crate::preprocessor::BlockKind;
+use pulldown_cmark::Event;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shlex::Shlex;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use wait_timeout::ChildExt;
+
+/// The error type handlers return.
+pub type HandlerError = Box<dyn Error + Send + Sync>;
+
+/// Custom rendering for blocks with a particular class, e.g. `{:.video src="intro.mp4"}`.
+///
+/// Handlers are registered by class with [`ClassyBuilder::handler`](crate::preprocessor::ClassyBuilder::handler).
+/// When a block has a class with a handler, the handler decides what replaces the block:
+///
+/// ```
+/// use mdbook_classy::handler::{Block, ClassHandler, HandlerError};
+///
+/// struct Video;
+///
+/// impl ClassHandler for Video {
+///     fn handle(&self, block: &Block<'_>) -> Result<Option<String>, HandlerError> {
+///         let src = block.annotation.attribute("src").ok_or("videos need a `src`")?;
+///         Ok(Some(format!("<video controls src=\"{}\"></video>", src)))
+///     }
+/// }
+/// ```
+pub trait ClassHandler: Send + Sync {
+    /// Return the markdown (or html) to put in place of the block and its annotation, an empty
+    /// string to remove it, or `None` to wrap it as usual.
+    fn handle(&self, block: &Block<'_>) -> Result<Option<String>, HandlerError>;
+}
+
+impl<F> ClassHandler for F
+where
+    F: Fn(&Block<'_>) -> Result<Option<String>, HandlerError> + Send + Sync,
+{
+    fn handle(&self, block: &Block<'_>) -> Result<Option<String>, HandlerError> {
+        self(block)
+    }
+}
+
+/// An annotated block, as passed to a [`ClassHandler`].
+#[derive(Debug)]
+pub struct Block<'a> {
+    /// The class the handler was registered for.
+    pub class: &'a str,
+    /// The block's annotation, with aliases resolved and disallowed attributes removed.
+    pub annotation: &'a Annotation,
+    pub kind: BlockKind,
+    /// The block's markdown as written in the chapter, without the annotation, and without the
+    /// blockquote markers or indentation the annotation's line has.
+    pub markdown: &'a str,
+    /// The block's events, without the annotation.
+    pub events: &'a [Event<'a>],
+    /// The chapter the block is in, when transforming a book.
+    pub chapter: Option<&'a ChapterInfo>,
+}
+
+/// What handlers are told about the chapter a block is in.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ChapterInfo {
+    pub name: String,
+    /// The chapter's source path, relative to the book's `src`. Draft chapters have none.
+    pub path: Option<PathBuf>,
+    /// The chapter's section number, e.g. `1.2.`, if it has one.
+    pub number: Option<String>,
+}
+
+/// The handlers registered for each class.
+#[derive(Clone, Default)]
+pub struct Handlers(BTreeMap<String, Arc<dyn ClassHandler>>);
+
+impl Handlers {
+    pub fn insert(&mut self, class: impl Into<String>, handler: impl ClassHandler + 'static) {
+        self.0.insert(class.into(), Arc::new(handler));
+    }
+
+    pub fn get(&self, class: &str) -> Option<&dyn ClassHandler> {
+        self.0.get(class).map(|handler| handler.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Handlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Handlers are equal when the same handlers are registered for the same classes.
+impl PartialEq for Handlers {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|((a, f), (b, g))| a == b && Arc::ptr_eq(f, g))
+    }
+}
+
+impl Eq for Handlers {}
+
+/// A handler that runs a program, configured in `book.toml`:
+///
+/// ```toml
+/// [preprocessor.classy.handlers.diagram]
+/// command = "python render.py"
+/// timeout = 10
+/// ```
+///
+/// For each block, the command is run with a JSON object on stdin:
+///
+/// ```json
+/// {"class": "diagram", "classes": ["diagram"], "id": null, "attributes": {"kind": "flow"},
+///  "block": "code", "markdown": "```\na -> b\n```",
+///  "chapter": {"name": "Design", "path": "design.md", "number": "2."}}
+/// ```
+///
+/// and whatever it prints to stdout replaces the block. Like mdbook's own preprocessor commands,
+/// its stderr is passed through, and exiting with a non-zero status is an error.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CommandHandler {
+    pub command: String,
+    /// Seconds to wait for each run before giving up on it.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_timeout() -> u64 {
+    30
+}
+
+impl CommandHandler {
+    pub fn new(command: impl Into<String>) -> Self {
+        CommandHandler {
+            command: command.into(),
+            timeout: default_timeout(),
+        }
+    }
+
+    fn input(block: &Block<'_>) -> String {
+        let attributes: BTreeMap<_, _> = block.annotation.attributes.iter().cloned().collect();
+        json!({
+            "class": block.class,
+            "classes": block.annotation.classes,
+            "id": block.annotation.id,
+            "attributes": attributes,
+            "block": block.kind,
+            "markdown": block.markdown,
+            "chapter": block.chapter,
+        })
+        .to_string()
+    }
+}
+
+impl ClassHandler for CommandHandler {
+    fn handle(&self, block: &Block<'_>) -> Result<Option<String>, HandlerError> {
+        let mut words = Shlex::new(&self.command);
+        let program = words.next().ok_or("the handler command is empty")?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| format!("couldn't run `{}`: {}", self.command, e))?;
+
+        // Feed stdin and drain stdout on their own threads, so a command that writes before it
+        // has read everything can't block us while we wait for it.
+        let mut stdin = child.stdin.take().expect("child has stdin");
+        let input = CommandHandler::input(block);
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let mut stdout = child.stdout.take().expect("child has stdout");
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        let status = match child.wait_timeout(Duration::from_secs(self.timeout)) {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                child.kill()?;
+                child.wait()?;
+                return Err(
+                    format!("`{}` didn't finish within {}s", self.command, self.timeout).into(),
+                );
+            }
+            Err(e) => {
+                // Don't leave the command running, or unreaped, when we can't wait for it.
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e.into());
+            }
+        };
+        // A command that exits without reading its input is fine.
+        let _ = writer.join();
+        let output = reader
+            .join()
+            .map_err(|_| "reading the command's output failed")??;
+        if !status.success() {
+            return Err(format!("`{}` failed with {}", self.command, status).into());
+        }
+        Ok(Some(output))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::annotation;
+
+    fn run(command: &str, timeout: u64) -> Result<Option<String>, HandlerError> {
+        let annotation = annotation::parse("{:.diagram #d1 kind=flow}").unwrap();
+        let block = Block {
+            class: "diagram",
+            annotation: &annotation,
+            kind: BlockKind::Paragraph,
+            markdown: "a -> b",
+            events: &[],
+            chapter: None,
+        };
+        CommandHandler {
+            command: command.to_string(),
+            timeout,
+        }
+        .handle(&block)
+    }
+
+    #[test]
+    fn sends_the_block_as_json() {
+        let output = run("cat", 10).unwrap().unwrap();
+        let input: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            input,
+            json!({
+                "class": "diagram",
+                "classes": ["diagram"],
+                "id": "d1",
+                "attributes": {"kind": "flow"},
+                "block": "paragraph",
+                "markdown": "a -> b",
+                "chapter": null,
+            })
+        );
+    }
+
+    #[test]
+    fn returns_stdout() {
+        let output = run("sh -c 'echo \"<b>hi</b>\"'", 10).unwrap();
+        assert_eq!(output.as_deref(), Some("<b>hi</b>\n"));
+    }
+
+    #[test]
+    fn reports_failures() {
+        let error = run("sh -c 'exit 3'", 10).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "`sh -c 'exit 3'` failed with exit status: 3"
+        );
+        assert!(run("no-such-command-for-classy", 10).is_err());
+        let error = run("sleep 5", 1).unwrap_err();
+        assert_eq!(error.to_string(), "`sleep 5` didn't finish within 1s");
+    }
+}
@@ -0,0 +1,257 @@
+use crate::handler::ChapterInfo;
+use crate::numbering::link_to;
+use crate::preprocessor::{off_events, ClassAnnotation};
+use pulldown_cmark::{CowStr, Event, LinkType, Tag};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The class listed by a `{{#classy-index class}}` directive, if `text` is one.
+fn directive(text: &str) -> Option<&str> {
+    let inner = text
+        .trim()
+        .strip_prefix("{{")?
+        .strip_suffix("}}")?
+        .trim()
+        .strip_prefix("#classy-index")?;
+    let class = inner.trim();
+    if inner.starts_with(char::is_whitespace) && !class.contains(char::is_whitespace) {
+        Some(class)
+    } else {
+        None
+    }
+}
+
+/// The paragraphs in `events` that are `{{#classy-index class}}` directives, with the class each
+/// lists.
+pub(crate) fn directives<'e, 'a: 'e, E>(
+    events: &'e [E],
+    event: impl Fn(&'e E) -> &'e Event<'a>,
+) -> Vec<(Range<usize>, String)> {
+    paragraphs(events, event, |text| directive(text).map(String::from))
+}
+
+/// The paragraphs in `events` made only of text that `parse` accepts, with what it makes of
+/// each. Paragraphs in `classy:off` regions are left out.
+pub(crate) fn paragraphs<'e, 'a: 'e, E, T>(
+    events: &'e [E],
+    event: impl Fn(&'e E) -> &'e Event<'a>,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Vec<(Range<usize>, T)> {
+    let off = off_events(events, &event);
+    let mut found = vec![];
+    for (i, e) in events.iter().enumerate() {
+        if off[i] || !matches!(event(e), Event::Start(Tag::Paragraph)) {
+            continue;
+        }
+        let mut text = String::new();
+        let mut j = i + 1;
+        while let Some(Event::Text(part)) = events.get(j).map(&event) {
+            text.push_str(part);
+            j += 1;
+        }
+        if let Some(Event::End(Tag::Paragraph)) = events.get(j).map(&event) {
+            if let Some(parsed) = parse(&text) {
+                found.push((i..j + 1, parsed));
+            }
+        }
+    }
+    found
+}
+
+/// A block listed by an index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub class: String,
+    pub title: String,
+    pub id: String,
+    pub chapter: String,
+    pub path: Option<PathBuf>,
+}
+
+/// The blocks with each of the classes `{{#classy-index class}}` directives list.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Index {
+    /// The classes some directive lists.
+    pub classes: BTreeSet<String>,
+    pub entries: Vec<Entry>,
+}
+
+impl Index {
+    /// Give the blocks with a listed class an id to link to, if they don't have one:
+    /// `exercise-2` for the second exercise in the chapter.
+    pub fn assign_ids(&self, class_annotations: &mut [ClassAnnotation]) {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for ca in class_annotations {
+            let class = match ca
+                .annotation
+                .classes
+                .iter()
+                .find(|class| self.classes.contains(*class))
+            {
+                Some(class) => class.clone(),
+                None => continue,
+            };
+            let count = counts.entry(class.clone()).or_default();
+            *count += 1;
+            if ca.annotation.id.is_none() {
+                ca.annotation.id = Some(format!("{}-{}", class, count));
+            }
+        }
+    }
+
+    /// Add the blocks with listed classes among `class_annotations`, which `assign_ids` has
+    /// given ids, in `chapter`.
+    pub fn add(
+        &mut self,
+        events: &[(Event, Range<usize>)],
+        class_annotations: &[ClassAnnotation],
+        chapter: &ChapterInfo,
+    ) {
+        for ca in class_annotations {
+            let id = match &ca.annotation.id {
+                Some(id) => id,
+                None => continue,
+            };
+            for class in &ca.annotation.classes {
+                if self.classes.contains(class) {
+                    self.entries.push(Entry {
+                        class: class.clone(),
+                        title: title(ca, events),
+                        id: id.clone(),
+                        chapter: chapter.name.clone(),
+                        path: chapter.path.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    /// A list of links to the blocks with `class`, from the chapter at `from`, in a sublist for
+    /// each chapter when there's more than one. `None` if there are none.
+    fn list(&self, class: &str, from: Option<&Path>) -> Option<Vec<Event<'static>>> {
+        let mut chapters: Vec<(&Entry, Vec<&Entry>)> = vec![];
+        for entry in self.entries.iter().filter(|entry| entry.class == class) {
+            match chapters.last_mut() {
+                Some((first, entries))
+                    if first.chapter == entry.chapter && first.path == entry.path =>
+                {
+                    entries.push(entry)
+                }
+                _ => chapters.push((entry, vec![entry])),
+            }
+        }
+        let mut events = vec![];
+        match chapters.as_slice() {
+            [] => return None,
+            [(_, entries)] if entries[0].path.as_deref() == from => {
+                list(&mut events, entries, from);
+            }
+            _ => {
+                events.push(Event::Start(Tag::List(None)));
+                for (first, entries) in &chapters {
+                    events.push(Event::Start(Tag::Item));
+                    match &first.path {
+                        Some(path) => link(&mut events, &first.chapter, link_to(from, path, None)),
+                        None => events.push(Event::Text(CowStr::from(first.chapter.clone()))),
+                    }
+                    list(&mut events, entries, from);
+                    events.push(Event::End(Tag::Item));
+                }
+                events.push(Event::End(Tag::List(None)));
+            }
+        }
+        Some(events)
+    }
+}
+
+fn list(events: &mut Vec<Event<'static>>, entries: &[&Entry], from: Option<&Path>) {
+    events.push(Event::Start(Tag::List(None)));
+    for entry in entries {
+        let url = match &entry.path {
+            Some(path) => link_to(from, path, Some(&entry.id)),
+            None => format!("#{}", entry.id),
+        };
+        events.push(Event::Start(Tag::Item));
+        link(events, &entry.title, url);
+        events.push(Event::End(Tag::Item));
+    }
+    events.push(Event::End(Tag::List(None)));
+}
+
+pub(crate) fn link(events: &mut Vec<Event<'static>>, text: &str, url: String) {
+    let tag = Tag::Link(LinkType::Inline, CowStr::from(url), CowStr::from(""));
+    events.extend([
+        Event::Start(tag.clone()),
+        Event::Text(CowStr::from(text.to_string())),
+        Event::End(tag),
+    ]);
+}
+
+/// What an index calls a block: its `title`, or else the text of its first line.
+pub(crate) fn title(ca: &ClassAnnotation, events: &[(Event, Range<usize>)]) -> String {
+    if let Some(title) = ca.annotation.attribute("title") {
+        return title.to_string();
+    }
+    let mut title = String::new();
+    for (event, _) in &events[ca.body_start..=ca.end] {
+        match event {
+            Event::Text(text) | Event::Code(text) => title.push_str(text),
+            Event::SoftBreak | Event::HardBreak | Event::End(_) if !title.is_empty() => break,
+            _ => {}
+        }
+    }
+    title.trim().to_string()
+}
+
+/// Replace the directive paragraphs in `events` with their lists, linked from the chapter at
+/// `from`. Directives for classes without blocks are dropped.
+pub(crate) fn expand_directives<'a>(
+    events: Vec<Event<'a>>,
+    index: &Index,
+    from: Option<&Path>,
+) -> Vec<Event<'a>> {
+    let found = directives(&events, |event| event);
+    replace(events, found, |class| {
+        index.list(&class, from).unwrap_or_default()
+    })
+}
+
+/// Replace the `found` ranges of `events`, which are in order and don't overlap, with what `with`
+/// makes of each.
+pub(crate) fn replace<'a, T>(
+    events: Vec<Event<'a>>,
+    found: Vec<(Range<usize>, T)>,
+    mut with: impl FnMut(T) -> Vec<Event<'static>>,
+) -> Vec<Event<'a>> {
+    let mut found = found.into_iter().peekable();
+    let mut replaced = Vec::with_capacity(events.len());
+    let mut skip_until = 0;
+    for (i, event) in events.into_iter().enumerate() {
+        if i < skip_until {
+            continue;
+        }
+        match found.next_if(|(range, _)| range.start == i) {
+            Some((range, found)) => {
+                skip_until = range.end;
+                replaced.extend(with(found));
+            }
+            None => replaced.push(event),
+        }
+    }
+    replaced
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_directives() {
+        assert_eq!(directive("{{#classy-index exercise}}"), Some("exercise"));
+        assert_eq!(directive("{{ #classy-index  warning }}\n"), Some("warning"));
+        assert_eq!(directive("{{#classy-indexes exercise}}"), None);
+        assert_eq!(directive("{{#classy-index a b}}"), None);
+        assert_eq!(directive("{{#include file.md}}"), None);
+    }
+}
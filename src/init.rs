@@ -0,0 +1,241 @@
+use crate::admonition;
+use mdbook::errors::Error;
+use std::fs;
+use std::path::Path;
+use toml_edit::{value, Array, DocumentMut, Item, Table};
+
+/// The stylesheet `init` creates, with a few classes to start from.
+pub const STARTER_CSS: &str = r#"/* Styles for mdbook-classy annotations such as `{:.note}`. */
+
+.note,
+.warning {
+    margin: 1em 0;
+    padding: 0.5em 1em;
+    border-left: 4px solid;
+    border-radius: 4px;
+}
+
+.note {
+    border-color: #4a90d9;
+    background: rgba(74, 144, 217, 0.1);
+}
+
+.warning {
+    border-color: #e0a030;
+    background: rgba(224, 160, 48, 0.1);
+}
+
+.centered {
+    text-align: center;
+}
+"#;
+
+/// Set up the book at `root` to use classy, returning a line describing each change made.
+///
+/// Adds `[preprocessor.classy]` to `book.toml`, creates `stylesheet` (relative to the book root)
+/// if it doesn't exist and lists it in `output.html.additional-css`. With `admonitions`, also
+/// turns them on and adds their stylesheet next to `stylesheet`. Running it again changes
+/// nothing.
+pub fn init(root: &Path, stylesheet: &str, admonitions: bool) -> Result<Vec<String>, Error> {
+    let config_path = root.join("book.toml");
+    let config = fs::read_to_string(&config_path)
+        .map_err(|e| Error::new(e).context(format!("reading {}", config_path.display())))?;
+    let admonitions_stylesheet = admonitions.then(|| admonitions_stylesheet(stylesheet));
+    let (config, mut changes) =
+        edit_config(&config, stylesheet, admonitions_stylesheet.as_deref())?;
+    if !changes.is_empty() {
+        fs::write(&config_path, config)?;
+    }
+
+    create(root, stylesheet, STARTER_CSS, &mut changes)?;
+    if let Some(admonitions_stylesheet) = &admonitions_stylesheet {
+        create(root, admonitions_stylesheet, admonition::CSS, &mut changes)?;
+    }
+    Ok(changes)
+}
+
+/// `classy-admonitions.css`, in the same directory as `stylesheet`.
+fn admonitions_stylesheet(stylesheet: &str) -> String {
+    match stylesheet.rfind('/') {
+        Some(slash) => format!("{}/classy-admonitions.css", &stylesheet[..slash]),
+        None => "classy-admonitions.css".to_string(),
+    }
+}
+
+/// Write `contents` to `path` under `root`, unless it already exists.
+fn create(root: &Path, path: &str, contents: &str, changes: &mut Vec<String>) -> Result<(), Error> {
+    let full_path = root.join(path);
+    if !full_path.exists() {
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&full_path, contents)?;
+        changes.push(format!("created {}", path));
+    }
+    Ok(())
+}
+
+/// Add classy and its stylesheet to the contents of a `book.toml`, keeping its comments and layout.
+/// With an `admonitions_stylesheet`, admonitions are turned on and it's added too.
+pub fn edit_config(
+    config: &str,
+    stylesheet: &str,
+    admonitions_stylesheet: Option<&str>,
+) -> Result<(String, Vec<String>), Error> {
+    let mut doc: DocumentMut = config
+        .parse()
+        .map_err(|e| Error::new(e).context("parsing book.toml"))?;
+    let mut changes = vec![];
+
+    // Run after `links` so annotations in `{{#include}}`d files are seen.
+    let use_links = doc
+        .get("build")
+        .and_then(|build| build.get("use-default-preprocessors"))
+        .and_then(Item::as_bool)
+        .unwrap_or(true)
+        || doc
+            .get("preprocessor")
+            .and_then(|preprocessors| preprocessors.get("links"))
+            .is_some();
+
+    let preprocessors = implicit_table(doc.as_table_mut(), "preprocessor")?;
+    if !preprocessors.contains_key("classy") {
+        let mut classy = Table::new();
+        if use_links {
+            classy["after"] = value(Array::from_iter(["links"]));
+        }
+        preprocessors.insert("classy", Item::Table(classy));
+        changes.push("added [preprocessor.classy] to book.toml".to_string());
+    }
+    if admonitions_stylesheet.is_some() {
+        let classy = preprocessors["classy"]
+            .as_table_like_mut()
+            .ok_or_else(|| Error::msg("`preprocessor.classy` in book.toml should be a table"))?;
+        if classy.get("admonitions").and_then(Item::as_bool) != Some(true) {
+            classy.insert("admonitions", value(true));
+            changes.push("set preprocessor.classy.admonitions in book.toml".to_string());
+        }
+    }
+
+    let output = implicit_table(doc.as_table_mut(), "output")?;
+    let html = implicit_table(output, "html")?;
+    let additional_css = html
+        .entry("additional-css")
+        .or_insert_with(|| value(Array::new()))
+        .as_array_mut()
+        .ok_or_else(|| Error::msg("output.html.additional-css should be an array"))?;
+    for stylesheet in std::iter::once(stylesheet).chain(admonitions_stylesheet) {
+        if !additional_css
+            .iter()
+            .any(|css| css.as_str() == Some(stylesheet))
+        {
+            additional_css.push(stylesheet);
+            changes.push(format!(
+                "added {} to output.html.additional-css in book.toml",
+                stylesheet
+            ));
+        }
+    }
+
+    Ok((doc.to_string(), changes))
+}
+
+/// Get the table `key` of `parent`, creating it as an implicit table (one without a header of
+/// its own) if it doesn't exist.
+fn implicit_table<'a>(parent: &'a mut Table, key: &str) -> Result<&'a mut Table, Error> {
+    parent
+        .entry(key)
+        .or_insert_with(|| {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            Item::Table(table)
+        })
+        .as_table_mut()
+        .ok_or_else(|| Error::msg(format!("`{}` in book.toml should be a table", key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_preprocessor_and_stylesheet() {
+        let config = r#"[book]
+title = "Example" # the title
+
+# Runs first.
+[preprocessor.index]
+
+[preprocessor.links]
+
+[output.html]
+git-repository-url = "https://example.com"
+"#;
+        let (edited, changes) = edit_config(config, "classy.css", None).unwrap();
+        assert_eq!(
+            edited,
+            r#"[book]
+title = "Example" # the title
+
+# Runs first.
+[preprocessor.index]
+
+[preprocessor.links]
+
+[preprocessor.classy]
+after = ["links"]
+
+[output.html]
+git-repository-url = "https://example.com"
+additional-css = ["classy.css"]
+"#
+        );
+        assert_eq!(changes.len(), 2);
+
+        let (again, changes) = edit_config(&edited, "classy.css", None).unwrap();
+        assert_eq!(again, edited);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn creates_missing_tables() {
+        let config = "[book]\ntitle = \"Example\"\n\n[build]\nuse-default-preprocessors = false\n";
+        let (edited, _) = edit_config(config, "theme/classy.css", None).unwrap();
+        assert_eq!(
+            edited,
+            "[book]\ntitle = \"Example\"\n\n[build]\nuse-default-preprocessors = false\n\
+             \n[preprocessor.classy]\n\n[output.html]\nadditional-css = [\"theme/classy.css\"]\n"
+        );
+    }
+
+    #[test]
+    fn turns_on_admonitions() {
+        let config = "[preprocessor.classy]\nafter = [\"links\"]\n";
+        let stylesheet = admonitions_stylesheet("theme/classy.css");
+        assert_eq!(stylesheet, "theme/classy-admonitions.css");
+        let (edited, changes) = edit_config(config, "theme/classy.css", Some(&stylesheet)).unwrap();
+        assert_eq!(
+            edited,
+            "[preprocessor.classy]\nafter = [\"links\"]\nadmonitions = true\n\n[output.html]\n\
+             additional-css = [\"theme/classy.css\", \"theme/classy-admonitions.css\"]\n"
+        );
+        assert_eq!(changes.len(), 3);
+
+        let (again, changes) = edit_config(&edited, "theme/classy.css", Some(&stylesheet)).unwrap();
+        assert_eq!(again, edited);
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn keeps_existing_settings() {
+        let config = "[preprocessor.classy]\ncommand = \"mdbook-classy\"\n\n\
+                      [output.html]\nadditional-css = [\"custom.css\"]\n";
+        let (edited, changes) = edit_config(config, "classy.css", None).unwrap();
+        assert_eq!(
+            edited,
+            "[preprocessor.classy]\ncommand = \"mdbook-classy\"\n\n\
+             [output.html]\nadditional-css = [\"custom.css\", \"classy.css\"]\n"
+        );
+        assert_eq!(changes.len(), 1);
+    }
+}
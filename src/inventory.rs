@@ -0,0 +1,296 @@
+use crate::chapter::chapter_annotation;
+use crate::config::Options;
+use crate::container::scan_containers;
+use crate::diagnostic::line_col;
+use crate::front_matter;
+use crate::preprocessor::{scan_with_options, BlockKind};
+use mdbook::book::{Book, BookItem};
+use mdbook::utils::new_cmark_parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Every class and id annotated in a book, and where.
+#[derive(Debug, Default, Serialize)]
+pub struct Inventory {
+    pub classes: BTreeMap<String, Usage>,
+    pub ids: BTreeMap<String, Usage>,
+}
+
+/// How often a class or id is used, broken down by chapter and by the kind of block annotated.
+#[derive(Debug, Default, Serialize)]
+pub struct Usage {
+    pub count: usize,
+    /// Uses per chapter, keyed by the chapter's source path (or its name for draft chapters).
+    pub chapters: BTreeMap<String, usize>,
+    pub blocks: BTreeMap<BlockKind, usize>,
+    pub locations: Vec<Location>,
+}
+
+/// Where an annotation is, with its 1-based line and column.
+#[derive(Debug, Clone, Serialize)]
+pub struct Location {
+    pub chapter: String,
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub block: BlockKind,
+}
+
+impl Location {
+    /// The chapter's source path, or its name for draft chapters.
+    pub fn chapter_key(&self) -> String {
+        match &self.path {
+            Some(path) => path.display().to_string(),
+            None => self.chapter.clone(),
+        }
+    }
+}
+
+impl Usage {
+    fn add(&mut self, location: Location) {
+        self.count += 1;
+        *self.chapters.entry(location.chapter_key()).or_default() += 1;
+        *self.blocks.entry(location.block).or_default() += 1;
+        self.locations.push(location);
+    }
+}
+
+/// Walk the chapters of `book` and record every class and id its annotations, containers and
+/// chapters use. Malformed annotations are skipped, and aliases count as the classes they stand
+/// for. Chapters with `classy = false` in their front matter are skipped too.
+pub fn inventory(book: &Book, options: &Options) -> Inventory {
+    let mut inventory = Inventory::default();
+    for item in book.iter() {
+        let chapter = match item {
+            BookItem::Chapter(chapter) => chapter,
+            _ => continue,
+        };
+        let (content, front_matter, _) = front_matter::split(&chapter.content);
+        let content = content.as_str();
+        let local_options;
+        let (options, chapter_classes) = match &front_matter {
+            Some(front_matter) if !front_matter.classy => continue,
+            Some(front_matter) => {
+                local_options = front_matter.apply(options);
+                (&local_options, front_matter.classes())
+            }
+            None => (options, vec![]),
+        };
+        let events: Vec<_> = new_cmark_parser(content, false)
+            .into_offset_iter()
+            .collect();
+        let (class_annotations, _) = scan_with_options(content, &events, options);
+        let (containers, _) = scan_containers(content, &events, &class_annotations, options);
+        let path = chapter.source_path.as_deref();
+        // A bad `chapter-classes` glob fails the build; here its classes just go uncounted.
+        let chapter_annotation =
+            chapter_annotation(content, &events, path, &chapter_classes, options)
+                .ok()
+                .map(|(chapter_annotation, _)| {
+                    let start = match &chapter_annotation.directive {
+                        Some(directive) => events[directive.start].1.start,
+                        None => 0,
+                    };
+                    (chapter_annotation.annotation, start)
+                });
+
+        let annotated = class_annotations
+            .iter()
+            .map(|ca| (&ca.annotation, ca.span.start, ca.block));
+        let fenced = containers.iter().map(|container| {
+            let start = events[container.open.start].1.start;
+            (&container.annotation, start, BlockKind::Container)
+        });
+        let whole = chapter_annotation
+            .iter()
+            .filter(|(annotation, _)| !annotation.classes.is_empty() || annotation.id.is_some())
+            .map(|(annotation, start)| (annotation, *start, BlockKind::Chapter));
+        for (annotation, start, block) in whole.chain(annotated).chain(fenced) {
+            let (line, column) = line_col(content, start);
+            let location = Location {
+                chapter: chapter.name.clone(),
+                path: chapter.source_path.clone(),
+                line,
+                column,
+                block,
+            };
+            for class in &annotation.classes {
+                inventory
+                    .classes
+                    .entry(class.clone())
+                    .or_default()
+                    .add(location.clone());
+            }
+            if let Some(id) = &annotation.id {
+                inventory
+                    .ids
+                    .entry(id.clone())
+                    .or_default()
+                    .add(location.clone());
+            }
+        }
+    }
+    inventory
+}
+
+/// A table of the classes and ids with their counts, followed by a line for each use.
+impl fmt::Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut summary = vec![vec![
+            "kind".to_string(),
+            "name".to_string(),
+            "uses".to_string(),
+            "chapters".to_string(),
+            "blocks".to_string(),
+        ]];
+        let mut uses = vec![vec![
+            "kind".to_string(),
+            "name".to_string(),
+            "location".to_string(),
+            "block".to_string(),
+        ]];
+        for (kind, names) in [("class", &self.classes), ("id", &self.ids)] {
+            for (name, usage) in names {
+                summary.push(vec![
+                    kind.to_string(),
+                    name.clone(),
+                    usage.count.to_string(),
+                    counts(&usage.chapters),
+                    counts(&usage.blocks),
+                ]);
+                for location in &usage.locations {
+                    uses.push(vec![
+                        kind.to_string(),
+                        name.clone(),
+                        format!(
+                            "{}:{}:{}",
+                            location.chapter_key(),
+                            location.line,
+                            location.column
+                        ),
+                        location.block.to_string(),
+                    ]);
+                }
+            }
+        }
+        write_table(f, &summary)?;
+        writeln!(f)?;
+        write_table(f, &uses)
+    }
+}
+
+/// `a (2), b (1)`
+fn counts<K: fmt::Display>(counts: &BTreeMap<K, usize>) -> String {
+    counts
+        .iter()
+        .map(|(key, count)| format!("{} ({})", key, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn write_table(f: &mut fmt::Formatter<'_>, rows: &[Vec<String>]) -> fmt::Result {
+    let mut widths = vec![0; rows[0].len()];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(f, "{}", cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mdbook::book::Chapter;
+
+    fn book() -> Book {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "One",
+            "{:.note #intro}\nText.\n\n{:.note .wide}\n- a list\n".to_string(),
+            "one.md",
+            vec![],
+        ));
+        book.push_item(Chapter::new(
+            "Two",
+            "# Two\n\n{:.note}\n> Quoted.\n\n{:.broken\nText.\n".to_string(),
+            "two.md",
+            vec![],
+        ));
+        book
+    }
+
+    #[test]
+    fn counts_classes_and_ids() {
+        let inventory = inventory(&book(), &Options::default());
+        let note = &inventory.classes["note"];
+        assert_eq!(note.count, 3);
+        assert_eq!(note.chapters["one.md"], 2);
+        assert_eq!(note.chapters["two.md"], 1);
+        assert_eq!(note.blocks[&BlockKind::Paragraph], 1);
+        assert_eq!(note.blocks[&BlockKind::List], 1);
+        assert_eq!(note.blocks[&BlockKind::BlockQuote], 1);
+        assert_eq!((note.locations[2].line, note.locations[2].column), (3, 1));
+        assert_eq!(inventory.classes["wide"].count, 1);
+        assert_eq!(inventory.ids["intro"].locations[0].chapter, "One");
+        assert!(!inventory.classes.contains_key("broken"));
+    }
+
+    #[test]
+    fn counts_chapter_and_container_classes() {
+        let mut book = Book::new();
+        book.push_item(Chapter::new(
+            "Appendix",
+            "+++\nclasses = \"appendix\"\n+++\n{::chapter #app}\n\n::: aside\n\nText.\n\n:::\n"
+                .to_string(),
+            "appendix.md",
+            vec![],
+        ));
+        book.push_item(Chapter::new(
+            "Off",
+            "+++\nclassy = false\n+++\n{:.note}\nText.\n".to_string(),
+            "off.md",
+            vec![],
+        ));
+        let options = Options {
+            chapter_classes: BTreeMap::from([("app*.md".to_string(), "wide".to_string())]),
+            ..Options::default()
+        };
+        let inventory = inventory(&book, &options);
+        let classes: Vec<_> = inventory.classes.keys().collect();
+        assert_eq!(classes, ["appendix", "aside", "wide"]);
+        let appendix = &inventory.classes["appendix"].locations[0];
+        assert_eq!((appendix.line, appendix.block), (4, BlockKind::Chapter));
+        assert_eq!(
+            inventory.classes["aside"].locations[0].block,
+            BlockKind::Container
+        );
+        assert_eq!(inventory.ids["app"].count, 1);
+    }
+
+    #[test]
+    fn json_and_table_output() {
+        let inventory = inventory(&book(), &Options::default());
+        let json = serde_json::to_value(&inventory).unwrap();
+        assert_eq!(json["classes"]["note"]["blocks"]["list"], 1);
+        assert_eq!(json["ids"]["intro"]["locations"][0]["path"], "one.md");
+
+        let table = inventory.to_string();
+        assert!(table.starts_with("kind   name   uses  chapters"));
+        assert!(table.contains(
+            "class  note   3     one.md (2), two.md (1)  paragraph (1), blockquote (1), list (1)"
+        ));
+        let last_use: Vec<_> = table.lines().last().unwrap().split_whitespace().collect();
+        assert_eq!(last_use, vec!["id", "intro", "one.md:1:1", "paragraph"]);
+    }
+}
@@ -1,3 +1,30 @@
 #![doc = include_str!("../README.md")]
 
+pub mod admonition;
+pub mod annotation;
+mod chapter;
+pub mod check;
+mod columns;
+pub mod config;
+mod container;
+pub mod css;
+mod details;
+pub mod diagnostic;
+mod error;
+mod exclude;
+pub mod explain;
+pub mod fmt;
+mod front_matter;
+mod glossary;
+pub mod handler;
+mod index;
+pub mod init;
+pub mod inventory;
+mod numbering;
 pub mod preprocessor;
+mod tabs;
+pub mod template;
+
+pub use config::Options;
+pub use error::ClassyError;
+pub use preprocessor::{transform, Output};
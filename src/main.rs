@@ -1,14 +1,27 @@
 use chrono::Local;
 use clap::{Parser, Subcommand};
 use env_logger::Builder;
-use log::{error, LevelFilter};
+use log::{error, warn, LevelFilter};
 use mdbook::errors::Error;
 use mdbook::preprocess::{CmdPreprocessor, Preprocessor};
-use std::io::Write;
+use mdbook::utils::render_markdown;
+use mdbook::{Config, MDBook};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
-use std::{env, io};
+use std::{env, fs, io};
 
-use mdbook_classy::preprocessor::Classy;
+use mdbook_classy::check::check;
+use mdbook_classy::config::{self, Options};
+use mdbook_classy::css;
+use mdbook_classy::diagnostic::{self, Format, Severity};
+use mdbook_classy::explain::explain;
+use mdbook_classy::fmt::format_book;
+use mdbook_classy::init::init;
+use mdbook_classy::inventory::inventory;
+use mdbook_classy::preprocessor::{check_mdbook_version, log_diagnostics, Classy};
+use mdbook_classy::template::register_templates;
+use mdbook_classy::{transform, Output};
 
 /// mdbook preprocessor to add support for admonitions
 #[derive(clap::Parser)]
@@ -20,30 +33,103 @@ use mdbook_classy::preprocessor::Classy;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// Run as a preprocessor even if mdbook's version looks incompatible
+    #[arg(long)]
+    skip_version_check: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Check whether a renderer is supported by this preprocessor
     Supports { renderer: String },
+    /// Add classy and a starter stylesheet to a book's `book.toml`
+    Init {
+        /// Root directory of the book
+        #[arg(default_value = ".")]
+        book_dir: PathBuf,
+        /// Stylesheet to create, relative to the book root
+        #[arg(long, default_value = "classy.css")]
+        stylesheet: String,
+        /// Turn on the built-in admonitions and add their stylesheet
+        #[arg(long)]
+        admonitions: bool,
+    },
+    /// Check a book's annotations without building it
+    Check {
+        /// Root directory of the book
+        #[arg(default_value = ".")]
+        book_dir: PathBuf,
+        /// Output format: text, json (one diagnostic per line) or sarif
+        #[arg(long, default_value = "text")]
+        format: Format,
+    },
+    /// Rewrite a book's annotations into the canonical `{:.class #id key="value"}` form
+    Fmt {
+        /// Root directory of the book
+        #[arg(default_value = ".")]
+        book_dir: PathBuf,
+        /// List the files that need formatting instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// List every class and id a book uses, where, and on which kinds of block
+    #[command(alias = "inventory")]
+    Stats {
+        /// Root directory of the book
+        #[arg(default_value = ".")]
+        book_dir: PathBuf,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print an empty css rule for each annotated class no stylesheet defines yet
+    Css {
+        /// Root directory of the book
+        #[arg(default_value = ".")]
+        book_dir: PathBuf,
+        /// Append the rules to this stylesheet instead of printing them
+        #[arg(long, value_name = "FILE")]
+        append: Option<PathBuf>,
+    },
+    /// Show how classy parses and transforms a markdown file, or stdin, step by step
+    #[command(alias = "debug")]
+    Explain {
+        /// Markdown file to read, or `-` for stdin
+        #[arg(default_value = "-")]
+        input: PathBuf,
+        /// Print JSON instead of text
+        #[arg(long)]
+        json: bool,
+    },
+    /// Transform a single markdown file, or stdin, and print the result
+    Render {
+        /// Markdown file to read, or `-` for stdin
+        #[arg(default_value = "-")]
+        input: PathBuf,
+        /// Print the html mdbook would render instead of markdown
+        #[arg(long)]
+        html: bool,
+        /// Read `[preprocessor.classy]` options from this book.toml
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Set a `[preprocessor.classy]` option, e.g. `--set diagnostics-format=json`
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        options: Vec<String>,
+    },
 }
 
 /// Housekeeping:
 /// 1. Check compatibility between preprocessor and mdbook
 /// 2. deserialize, run the transformation, and reserialize.
-fn handle_preprocessing(pre: &dyn Preprocessor) -> Result<(), Error> {
+fn handle_preprocessing(pre: &dyn Preprocessor, skip_version_check: bool) -> Result<(), Error> {
     let (ctx, book) = CmdPreprocessor::parse_input(io::stdin())?;
 
-    if ctx.mdbook_version != mdbook::MDBOOK_VERSION {
-        // We should probably use the `semver` crate to check compatibility
-        // here...
-        error!(
-            "Warning: The {} plugin was built against version {} of mdbook, \
-             but we're being called from version {}",
-            pre.name(),
-            mdbook::MDBOOK_VERSION,
-            ctx.mdbook_version
-        );
+    if let Err(e) = check_mdbook_version(&ctx.mdbook_version) {
+        if skip_version_check || Options::from_context(&ctx)?.skip_version_check {
+            warn!("{}", e);
+        } else {
+            return Err(e);
+        }
     }
 
     let processed_book = pre.run(&ctx, book)?;
@@ -52,6 +138,14 @@ fn handle_preprocessing(pre: &dyn Preprocessor) -> Result<(), Error> {
     Ok(())
 }
 
+/// The options in `book.toml` in the current directory, where mdbook is usually run, with
+/// mdbook's `MDBOOK_*` environment overrides. mdbook doesn't tell `supports` which book it's for.
+fn book_options() -> Options {
+    let mut book_config = Config::from_disk("book.toml").unwrap_or_default();
+    book_config.update_from_env();
+    Options::from_config(&book_config).unwrap_or_default()
+}
+
 /// Check to see if we support the processor (classy only supports html right now)
 fn handle_supports(pre: &dyn Preprocessor, renderer: &str) -> ! {
     let supported = pre.supports_renderer(renderer);
@@ -63,6 +157,160 @@ fn handle_supports(pre: &dyn Preprocessor, renderer: &str) -> ! {
     }
 }
 
+/// Wire classy into the book, reporting what changed.
+fn handle_init(book_dir: &Path, stylesheet: &str, admonitions: bool) -> Result<(), Error> {
+    let changes = init(book_dir, stylesheet, admonitions)?;
+    if changes.is_empty() {
+        println!("{} already uses classy", book_dir.display());
+    }
+    for change in &changes {
+        println!("{}", change);
+    }
+    Ok(())
+}
+
+/// Lint the book and report its problems in the requested format. Exits non-zero if any are errors.
+fn handle_check(book_dir: &Path, format: Format) -> Result<(), Error> {
+    let diagnostics = check(book_dir)?;
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    diagnostic::write(&mut io::stdout(), &diagnostics, format)?;
+
+    if errors > 0 {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Format the book's chapters in place. With `--check`, exits non-zero if any need formatting.
+fn handle_fmt(book_dir: &Path, check: bool) -> Result<(), Error> {
+    let changed = format_book(book_dir, check)?;
+    for path in &changed {
+        if check {
+            println!("{} needs formatting", path.display());
+        } else {
+            println!("formatted {}", path.display());
+        }
+    }
+
+    if check && !changed.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print the book's class and id inventory.
+fn handle_stats(book_dir: &Path, json: bool) -> Result<(), Error> {
+    let md = MDBook::load(book_dir)?;
+    let inventory = inventory(&md.book, &Options::from_config(&md.config)?);
+    if json {
+        serde_json::to_writer_pretty(io::stdout(), &inventory)?;
+        println!();
+    } else {
+        print!("{}", inventory);
+    }
+    Ok(())
+}
+
+/// Print stub rules for the book's unstyled classes, or append them to a stylesheet.
+fn handle_css(book_dir: &Path, append: Option<&Path>) -> Result<(), Error> {
+    let md = MDBook::load(book_dir)?;
+    let existing = match append {
+        Some(path) => fs::read_to_string(path)
+            .map_err(|e| Error::new(e).context(format!("reading {}", path.display())))?,
+        None => String::new(),
+    };
+    let rules = css::stubs(&md, &css::classes(&existing))?;
+    let stubs = rules.join("\n");
+
+    match append {
+        Some(path) if !rules.is_empty() => {
+            let mut file = fs::OpenOptions::new().append(true).open(path)?;
+            if !existing.is_empty() && !existing.ends_with("\n\n") {
+                let newlines = if existing.ends_with('\n') {
+                    "\n"
+                } else {
+                    "\n\n"
+                };
+                file.write_all(newlines.as_bytes())?;
+            }
+            file.write_all(stubs.as_bytes())?;
+            let noun = if rules.len() == 1 { "rule" } else { "rules" };
+            println!("added {} {} to {}", rules.len(), noun, path.display());
+        }
+        Some(path) => println!("{} already styles every class", path.display()),
+        None => print!("{}", stubs),
+    }
+    Ok(())
+}
+
+/// Print the events, annotations and splice plan for one file.
+fn handle_explain(input: &Path, json: bool) -> Result<(), Error> {
+    let explanation = explain(&read_input(input)?, &Options::default())?;
+    if json {
+        serde_json::to_writer_pretty(io::stdout(), &explanation)?;
+        println!();
+    } else {
+        print!("{}", explanation);
+    }
+    Ok(())
+}
+
+/// Read a file, or stdin if `input` is `-`.
+fn read_input(input: &Path) -> Result<String, Error> {
+    if input == Path::new("-") {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        Ok(content)
+    } else {
+        Ok(fs::read_to_string(input)?)
+    }
+}
+
+/// Run the preprocessor's transform on one file, configured like a build of the book would be.
+fn handle_render(
+    input: &Path,
+    html: bool,
+    config_file: Option<&Path>,
+    overrides: &[String],
+) -> Result<(), Error> {
+    let mut book_config = match config_file {
+        Some(path) => Config::from_disk(path)?,
+        None => Config::default(),
+    };
+    for assignment in overrides {
+        config::set_option(&mut book_config, assignment)?;
+    }
+    let root = config_file.and_then(Path::parent).unwrap_or(Path::new("."));
+    let mut options = Options::from_config(&book_config)?;
+    register_templates(&mut options, root)?;
+
+    let stdin = input == Path::new("-");
+    let content = read_input(input)?;
+
+    let Output {
+        content: output,
+        mut diagnostics,
+    } = transform(&content, &options)?;
+    if !stdin {
+        for diagnostic in &mut diagnostics {
+            diagnostic.path = Some(input.to_path_buf());
+        }
+    }
+    log_diagnostics(&diagnostics);
+    options.write_diagnostics(root, &diagnostics)?;
+
+    if html {
+        let curly_quotes = book_config.html_config().unwrap_or_default().curly_quotes;
+        print!("{}", render_markdown(&output, curly_quotes));
+    } else {
+        println!("{}", output);
+    }
+    Ok(())
+}
+
 fn init_logger() {
     let mut builder = Builder::new();
 
@@ -100,14 +348,66 @@ fn main() {
 
     match args.command {
         None => {
-            if let Err(e) = handle_preprocessing(&preprocessor) {
+            if let Err(e) = handle_preprocessing(&preprocessor, args.skip_version_check) {
                 error!("{}", e);
                 process::exit(1);
             }
         }
         Some(Commands::Supports { renderer }) => {
+            let preprocessor = Classy::builder().options(book_options()).build();
             handle_supports(&preprocessor, &renderer);
         }
+        Some(Commands::Init {
+            book_dir,
+            stylesheet,
+            admonitions,
+        }) => {
+            if let Err(e) = handle_init(&book_dir, &stylesheet, admonitions) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Check { book_dir, format }) => {
+            if let Err(e) = handle_check(&book_dir, format) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Fmt { book_dir, check }) => {
+            if let Err(e) = handle_fmt(&book_dir, check) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Stats { book_dir, json }) => {
+            if let Err(e) = handle_stats(&book_dir, json) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Css { book_dir, append }) => {
+            if let Err(e) = handle_css(&book_dir, append.as_deref()) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Explain { input, json }) => {
+            if let Err(e) = handle_explain(&input, json) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
+        Some(Commands::Render {
+            input,
+            html,
+            config,
+            options,
+        }) => {
+            if let Err(e) = handle_render(&input, html, config.as_deref(), &options) {
+                error!("{}", e);
+                process::exit(1);
+            }
+        }
     }
 }
 
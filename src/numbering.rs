@@ -0,0 +1,272 @@
+use crate::config::Options;
+use crate::diagnostic::{self, Diagnostic};
+use crate::preprocessor::{off_events, ClassAnnotation};
+use pulldown_cmark::{CowStr, Event, LinkType, Tag};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+/// The labels of the numbered blocks among `class_annotations`, such as "Theorem 3.2" for the
+/// second theorem in chapter 3. Each numbered class is counted separately.
+pub(crate) fn labels(
+    class_annotations: &[ClassAnnotation],
+    chapter_number: Option<&str>,
+    options: &Options,
+) -> Vec<Option<String>> {
+    // mdbook writes section numbers as `3.` or `3.2.`.
+    let prefix = chapter_number.filter(|number| number.ends_with('.'));
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    class_annotations
+        .iter()
+        .map(|ca| {
+            let (class, name) = ca
+                .annotation
+                .classes
+                .iter()
+                .find_map(|class| Some((class.as_str(), options.numbered.get(class)?)))?;
+            let count = counts.entry(class).or_default();
+            *count += 1;
+            Some(format!("{} {}{}", name, prefix.unwrap_or(""), count))
+        })
+        .collect()
+}
+
+/// A numbered block with an id, which `[@id]` refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Target {
+    pub label: String,
+    /// The source path of the chapter it's in, if it's in a chapter with one.
+    pub path: Option<PathBuf>,
+}
+
+/// The numbered blocks with ids in a book, or in a single chapter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct References(BTreeMap<String, Target>);
+
+impl References {
+    /// Add the numbered blocks among `class_annotations`, with their `labels`, in the chapter
+    /// at `path`.
+    pub fn add(
+        &mut self,
+        class_annotations: &[ClassAnnotation],
+        labels: &[Option<String>],
+        path: Option<&Path>,
+    ) {
+        for (ca, label) in class_annotations.iter().zip(labels) {
+            if let (Some(id), Some(label)) = (&ca.annotation.id, label) {
+                self.0.entry(id.clone()).or_insert_with(|| Target {
+                    label: label.clone(),
+                    path: path.map(Path::to_path_buf),
+                });
+            }
+        }
+    }
+
+    /// The label of the block `id`, and a link to it from the chapter at `from`.
+    pub fn link(&self, id: &str, from: Option<&Path>) -> Option<(String, String)> {
+        let target = self.0.get(id)?;
+        let url = match &target.path {
+            Some(to) => link_to(from, to, Some(id)),
+            None => format!("#{}", id),
+        };
+        Some((target.label.clone(), url))
+    }
+}
+
+/// A link from the chapter at `from` to the chapter at `to`, or to the element `id` in it.
+pub(crate) fn link_to(from: Option<&Path>, to: &Path, id: Option<&str>) -> String {
+    let page = match from {
+        Some(from) if from == to && id.is_some() => String::new(),
+        Some(from) => relative(from, to),
+        None => to.to_string_lossy().replace('\\', "/"),
+    };
+    match id {
+        Some(id) => format!("{}#{}", page, id),
+        None => page,
+    }
+}
+
+/// The path to the file `to` from the file `from`, with `/` separators, as links are written.
+fn relative(from: &Path, to: &Path) -> String {
+    let from: Vec<_> = from
+        .parent()
+        .map(|dir| dir.components().collect())
+        .unwrap_or_default();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    for component in &to[common..] {
+        if let Component::Normal(part) = component {
+            parts.push(part.to_string_lossy().into_owned());
+        }
+    }
+    parts.join("/")
+}
+
+/// The `[@id]` references in `text`, with their byte ranges.
+fn references(text: &str) -> Vec<(Range<usize>, &str)> {
+    let mut found = vec![];
+    let mut from = 0;
+    while let Some(start) = text[from..].find("[@").map(|i| from + i) {
+        let id_start = start + 2;
+        let id_len = text[id_start..]
+            .find(|c: char| !(c.is_alphanumeric() || "-_:.".contains(c)))
+            .unwrap_or(text.len() - id_start);
+        let id_end = id_start + id_len;
+        if id_len > 0 && text[id_end..].starts_with(']') {
+            found.push((start..id_end + 1, &text[id_start..id_end]));
+            from = id_end + 1;
+        } else {
+            from = id_start;
+        }
+    }
+    found
+}
+
+/// The text events outside code blocks, links, images and `classy:off` regions, merged into runs,
+/// with the range of events in each.
+pub(crate) fn text_runs<'e, 'a: 'e, E>(
+    events: &'e [E],
+    event: impl Fn(&'e E) -> &'e Event<'a>,
+) -> Vec<(String, Range<usize>)> {
+    let off = off_events(events, &event);
+    let mut runs = vec![];
+    let mut run: Option<(String, Range<usize>)> = None;
+    let mut depth = 0;
+    for (i, e) in events.iter().enumerate() {
+        if off[i] {
+            runs.extend(run.take());
+            continue;
+        }
+        match event(e) {
+            Event::Start(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => depth += 1,
+            Event::End(Tag::CodeBlock(_) | Tag::Link(..) | Tag::Image(..)) => depth -= 1,
+            Event::Text(text) if depth == 0 => {
+                let (merged, range) = run.get_or_insert_with(|| (String::new(), i..i));
+                merged.push_str(text);
+                range.end = i + 1;
+                continue;
+            }
+            _ => {}
+        }
+        runs.extend(run.take());
+    }
+    runs.extend(run);
+    runs
+}
+
+/// Report the references in `content` to blocks that aren't numbered or don't exist.
+pub(crate) fn undefined_references(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    defined: impl Fn(&str) -> bool,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for (text, run) in text_runs(events, |(event, _)| event) {
+        let source = events[run.start].1.start..events[run.end - 1].1.end;
+        for (_, id) in references(&text) {
+            if defined(id) {
+                continue;
+            }
+            let reference = format!("[@{}]", id);
+            let span = match content[source.clone()].find(&reference) {
+                Some(at) => source.start + at..source.start + at + reference.len(),
+                None => source.clone(),
+            };
+            diagnostics.push(
+                Diagnostic::warning(
+                    diagnostic::UNDEFINED_REFERENCE,
+                    format!("no numbered block has the id `{}`", id),
+                    content,
+                    span,
+                )
+                .with_fix(format!(
+                    "give a block with a numbered class `#{}`, or fix the reference",
+                    id
+                )),
+            );
+        }
+    }
+    diagnostics
+}
+
+/// Replace the `[@id]` references in `events` with links labelled like "Theorem 3.2", using
+/// `link` to find each label and url. References it doesn't know are left as they are.
+pub(crate) fn link_references<'a>(
+    events: Vec<Event<'a>>,
+    link: impl Fn(&str) -> Option<(String, String)>,
+) -> Vec<Event<'a>> {
+    link_text(events, |text| {
+        references(text)
+            .into_iter()
+            .filter_map(|(range, id)| {
+                let (label, url) = link(id)?;
+                Some((range, label, url))
+            })
+            .collect()
+    })
+}
+
+/// Replace parts of the text in `events` with links. `find` gives the byte ranges of the parts
+/// of each run of text to replace, in order, with the label and url of each link.
+pub(crate) fn link_text<'a>(
+    events: Vec<Event<'a>>,
+    mut find: impl FnMut(&str) -> Vec<(Range<usize>, String, String)>,
+) -> Vec<Event<'a>> {
+    let mut runs = text_runs(&events, |event| event).into_iter().peekable();
+    let mut linked = Vec::with_capacity(events.len());
+    let mut skip_until = 0;
+    for (i, event) in events.into_iter().enumerate() {
+        if i < skip_until {
+            continue;
+        }
+        let (text, run) = match runs.next_if(|(_, run)| run.start == i) {
+            Some(run) => run,
+            None => {
+                linked.push(event);
+                continue;
+            }
+        };
+        skip_until = run.end;
+        let mut last = 0;
+        for (range, label, url) in find(&text) {
+            if range.start > last {
+                linked.push(Event::Text(CowStr::from(
+                    text[last..range.start].to_string(),
+                )));
+            }
+            let tag = Tag::Link(LinkType::Inline, CowStr::from(url), CowStr::from(""));
+            linked.extend([
+                Event::Start(tag.clone()),
+                Event::Text(CowStr::from(label)),
+                Event::End(tag),
+            ]);
+            last = range.end;
+        }
+        if last < text.len() {
+            linked.push(Event::Text(CowStr::from(text[last..].to_string())));
+        }
+    }
+    linked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_references() {
+        let text = "See [@thm-euler], [@] and [@fig:1.2] but not [@a b] or [x].";
+        let found: Vec<_> = references(text).into_iter().map(|(_, id)| id).collect();
+        assert_eq!(found, ["thm-euler", "fig:1.2"]);
+    }
+
+    #[test]
+    fn relative_paths() {
+        let path = |from: &str, to: &str| relative(Path::new(from), Path::new(to));
+        assert_eq!(path("intro.md", "maths/euler.md"), "maths/euler.md");
+        assert_eq!(path("maths/euler.md", "intro.md"), "../intro.md");
+        assert_eq!(path("maths/euler.md", "maths/gauss.md"), "gauss.md");
+        assert_eq!(path("a/b/c.md", "a/d/e.md"), "../d/e.md");
+    }
+}
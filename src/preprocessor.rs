@@ -1,16 +1,137 @@
-use log::error;
-use mdbook::book::{Book, Chapter};
+use crate::admonition;
+use crate::annotation::{self, escape, Annotation};
+use crate::chapter::chapter_annotation;
+use crate::config::{Options, Strategy};
+use crate::container::{layout, scan_containers, Fence, Layout};
+use crate::details;
+use crate::diagnostic::{self, Diagnostic, Format, Severity};
+use crate::error::ClassyError;
+use crate::exclude::exclude;
+use crate::front_matter::{self, FrontMatter};
+use crate::glossary::{self, Glossary};
+use crate::handler::{Block, ChapterInfo, ClassHandler};
+use crate::index::{self, expand_directives, Index};
+use crate::numbering::{self, link_references, undefined_references, References};
+use crate::template::register_templates;
+use log::{error, warn};
+use mdbook::book::{Book, BookItem, Chapter};
 use mdbook::errors::Error;
 use mdbook::preprocess::{Preprocessor, PreprocessorContext};
 use mdbook::utils::new_cmark_parser;
 use pulldown_cmark::{CowStr, Event, Tag};
-
+use semver::{Version, VersionReq};
+use serde::Serialize;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// The preprocessor. Its options are the defaults for every book, and each book's
+/// `[preprocessor.classy]` table is merged over them.
 #[derive(Default)]
-pub struct Classy;
+pub struct Classy {
+    options: Options,
+}
 
 impl Classy {
     pub fn new() -> Classy {
-        Classy
+        Classy::default()
+    }
+
+    pub fn builder() -> ClassyBuilder {
+        ClassyBuilder::default()
+    }
+}
+
+/// Configures a [`Classy`] for embedding in a program that builds books with mdbook:
+///
+/// ```
+/// use mdbook_classy::config::Strategy;
+/// use mdbook_classy::preprocessor::Classy;
+///
+/// let classy = Classy::builder()
+///     .wrapper("section")
+///     .strict(true)
+///     .alias("tip", "callout callout-tip")
+///     .strategy("markdown", Strategy::Strip)
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct ClassyBuilder {
+    options: Options,
+}
+
+impl ClassyBuilder {
+    /// Start from these options rather than the defaults.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn wrapper(mut self, tag: impl Into<String>) -> Self {
+        self.options.wrapper = tag.into();
+        self
+    }
+
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    pub fn allowed_attributes<I, S>(mut self, attributes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.allowed_attributes = Some(attributes.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Make the class `name` stand for `classes`, a space separated list.
+    pub fn alias(mut self, name: impl Into<String>, classes: impl Into<String>) -> Self {
+        self.options.aliases.insert(name.into(), classes.into());
+        self
+    }
+
+    /// Render the built-in admonition classes, such as `note` and `warning`, with a title and icon.
+    pub fn admonitions(mut self, admonitions: bool) -> Self {
+        self.options.admonitions = admonitions;
+        self
+    }
+
+    pub fn strategy(mut self, renderer: impl Into<String>, strategy: Strategy) -> Self {
+        self.options.strategies.insert(renderer.into(), strategy);
+        self
+    }
+
+    pub fn diagnostics_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.diagnostics_file = Some(path.into());
+        self
+    }
+
+    pub fn diagnostics_format(mut self, format: Format) -> Self {
+        self.options.diagnostics_format = format;
+        self
+    }
+
+    /// Render blocks with the class `class` with `handler`.
+    pub fn handler(
+        mut self,
+        class: impl Into<String>,
+        handler: impl ClassHandler + 'static,
+    ) -> Self {
+        self.options.handlers.insert(class, handler);
+        self
+    }
+
+    pub fn skip_version_check(mut self, skip: bool) -> Self {
+        self.options.skip_version_check = skip;
+        self
+    }
+
+    pub fn build(self) -> Classy {
+        Classy {
+            options: self.options,
+        }
     }
 }
 
@@ -19,120 +140,1066 @@ impl Preprocessor for Classy {
         "classy"
     }
 
-    fn run(&self, _ctx: &PreprocessorContext, mut book: Book) -> Result<Book, Error> {
+    fn run(&self, ctx: &PreprocessorContext, mut book: Book) -> Result<Book, Error> {
+        let mut options = self.options.merged(&ctx.config)?;
+        // Excluded blocks go for every renderer, and before anything else sees them.
+        if !options.exclude_classes.is_empty() {
+            book.for_each_mut(|item| {
+                if let BookItem::Chapter(chapter) = item {
+                    chapter.content = exclude(&chapter.content, &options);
+                }
+            });
+        }
+        let strategy = match options.strategies.get(&ctx.renderer) {
+            Some(strategy) => *strategy,
+            None => {
+                // Front matter is classy's, so it goes whether or not the annotations are used.
+                book.for_each_mut(|item| {
+                    if let BookItem::Chapter(chapter) = item {
+                        chapter.content = front_matter::split(&chapter.content).0;
+                    }
+                });
+                return Ok(book);
+            }
+        };
+        register_templates(&mut options, &ctx.root)?;
+        // Numbers and ids come from the whole book, so references and indexes can point to other
+        // chapters.
+        let data = BookData::from_book(&book, &options);
+        let mut diagnostics = vec![];
+        let mut strict_failure = false;
+        let mut failure = None;
         book.for_each_mut(|book| {
             if let mdbook::BookItem::Chapter(chapter) = book {
-                if let Err(e) = classy(chapter) {
-                    error!("classy error: {:?}", e);
-                }
+                let found = match classy(chapter, &data, &options, strategy) {
+                    Ok(found) => found,
+                    Err(ClassyError::Strict(found)) => {
+                        strict_failure = true;
+                        found
+                    }
+                    Err(e) => {
+                        let context = format!("processing chapter `{}`", chapter.name);
+                        failure.get_or_insert(Error::new(e).context(context));
+                        return;
+                    }
+                };
+                let path = chapter
+                    .source_path
+                    .as_ref()
+                    .map(|path| ctx.config.book.src.join(path));
+                diagnostics.extend(found.into_iter().map(|diagnostic| Diagnostic {
+                    path: path.clone(),
+                    ..diagnostic
+                }));
             }
         });
+
+        log_diagnostics(&diagnostics);
+        options.write_diagnostics(&ctx.root, &diagnostics)?;
+        if let Some(failure) = failure {
+            return Err(failure);
+        }
+        if strict_failure {
+            return Err(Error::msg(
+                "annotations have errors, and `preprocessor.classy.strict` is set",
+            ));
+        }
         Ok(book)
     }
 
     fn supports_renderer(&self, renderer: &str) -> bool {
-        renderer == "html"
+        // Excluded blocks are left out of every renderer's output.
+        self.options.strategies.contains_key(renderer) || !self.options.exclude_classes.is_empty()
     }
 }
 
-#[derive(Debug)]
-struct ClassAnnotation {
-    pub class: String,
-    pub _index: usize,
-    pub paragraph_start: usize,
-    pub paragraph_end: Option<usize>,
+/// Check that the mdbook calling us speaks the same preprocessor protocol as the one we were built
+/// against. Releases with the same minor version (or major, from 1.0) are compatible.
+pub fn check_mdbook_version(version: &str) -> Result<(), Error> {
+    let built = Version::parse(mdbook::MDBOOK_VERSION)?;
+    let requirement = if built.major == 0 {
+        VersionReq::parse(&format!("^0.{}", built.minor))?
+    } else {
+        VersionReq::parse(&format!("^{}", built.major))?
+    };
+    let found = Version::parse(version)
+        .map_err(|e| Error::new(e).context(format!("invalid mdbook version `{}`", version)))?;
+    if requirement.matches(&found) {
+        Ok(())
+    } else {
+        Err(Error::msg(format!(
+            "mdbook {} is incompatible with this mdbook-classy, which was built against mdbook {}. \
+             Install a build for mdbook {}, or pass `--skip-version-check` or set \
+             `preprocessor.classy.skip-version-check = true` to try anyway",
+            found,
+            mdbook::MDBOOK_VERSION,
+            found
+        )))
+    }
 }
 
-/// This is where the markdown transformation actually happens.
-/// Take paragraphs beginning with `{:.class-name}` and give them special rendering.
-/// Mutation: the payload here is that it edits chapter.content.
-fn classy(chapter: &mut Chapter) -> Result<(), Error> {
-    // 1. Parse the inbound markdown into an Event vector.
-    let incoming_events: Vec<Event> = new_cmark_parser(&chapter.content, false).collect();
+pub fn log_diagnostics(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        match diagnostic.severity {
+            Severity::Error => error!("{}", diagnostic),
+            Severity::Warning => warn!("{}", diagnostic),
+        }
+    }
+}
 
-    // 2. Find paragraphs beginning with the class annotator `{:.class-name}` and record their information in
-    // a vector of ClassAnnotation structs.
-    let mut class_annotations: Vec<ClassAnnotation> = vec![];
-    for i in 0..incoming_events.len() {
-        let event = &incoming_events[i];
-        match *event {
-            Event::Text(CowStr::Borrowed(text)) => {
-                if i > 0 {
-                    if let Event::Start(Tag::Paragraph) = incoming_events[i - 1] {
-                        let v: Vec<_> = text.split("").collect();
-                        let len_v = v.len();
-
-                        if len_v > 4 && v[..4].join("") == "{:." && v[(len_v - 2)..].join("") == "}"
-                        {
-                            // TODO: Look into why cmark splits paragraphs that start with '['
-
-                            let class = v[4..(len_v - 2)].join("");
-                            class_annotations.push(ClassAnnotation {
-                                class,
-                                _index: i,
-                                paragraph_start: i - 1,
-                                paragraph_end: None,
-                            })
-                        }
-                    }
-                }
+/// The kind of markdown block an annotation applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockKind {
+    Paragraph,
+    Heading,
+    BlockQuote,
+    #[serde(rename = "code")]
+    CodeBlock,
+    List,
+    Table,
+    /// A `:::` container.
+    Container,
+    /// A whole chapter, with classes from `{::chapter}`, `chapter-classes` or front matter.
+    Chapter,
+}
+
+impl BlockKind {
+    fn of(tag: &Tag) -> Option<BlockKind> {
+        match tag {
+            Tag::Paragraph => Some(BlockKind::Paragraph),
+            Tag::Heading(..) => Some(BlockKind::Heading),
+            Tag::BlockQuote => Some(BlockKind::BlockQuote),
+            Tag::CodeBlock(_) => Some(BlockKind::CodeBlock),
+            Tag::List(_) => Some(BlockKind::List),
+            Tag::Table(_) => Some(BlockKind::Table),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BlockKind::Paragraph => "paragraph",
+            BlockKind::Heading => "heading",
+            BlockKind::BlockQuote => "blockquote",
+            BlockKind::CodeBlock => "code",
+            BlockKind::List => "list",
+            BlockKind::Table => "table",
+            BlockKind::Container => "container",
+            BlockKind::Chapter => "chapter",
+        })
+    }
+}
+
+/// An annotation and the events of the block it applies to.
+///
+/// The annotation either starts the paragraph it applies to, or is alone in a paragraph directly
+/// followed by another block, which it then applies to:
+///
+/// ```markdown
+/// {:.warning}
+/// - a list that is styled as a whole
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct ClassAnnotation {
+    pub annotation: Annotation,
+    /// Byte range of the annotation text in the chapter source.
+    pub span: Range<usize>,
+    pub block: BlockKind,
+    /// Index of the paragraph event that holds the annotation.
+    pub start: usize,
+    /// Index of the first event of the annotated content, after the annotation line.
+    pub body_start: usize,
+    /// Index of the event that ends the annotated block.
+    pub end: usize,
+}
+
+/// Whether an html event is a `<!-- classy:on -->` comment (`Some(true)`), a
+/// `<!-- classy:off -->` one (`Some(false)`), or neither.
+fn switch(html: &str) -> Option<bool> {
+    let comment = html.trim().strip_prefix("<!--")?.strip_suffix("-->")?;
+    match comment.trim() {
+        "classy:on" => Some(true),
+        "classy:off" => Some(false),
+        _ => None,
+    }
+}
+
+/// The byte ranges between `<!-- classy:off -->` and `<!-- classy:on -->` comments, or the end
+/// of the chapter, where annotations are left as they are.
+pub(crate) fn off_ranges(events: &[(Event, Range<usize>)]) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut off = None;
+    for (event, range) in events {
+        if let Event::Html(html) = event {
+            match switch(html) {
+                Some(false) if off.is_none() => off = Some(range.end),
+                Some(true) => ranges.extend(off.take().map(|start| start..range.start)),
+                _ => {}
             }
-            Event::End(Tag::Paragraph) => {
-                let last = class_annotations.last_mut();
-                if let Some(class_command) = last {
-                    if class_command.paragraph_end.is_none() {
-                        class_command.paragraph_end = Some(i);
+        }
+    }
+    ranges.extend(off.map(|start| start..usize::MAX));
+    ranges
+}
+
+/// Whether each of `events` is between `<!-- classy:off -->` and `<!-- classy:on -->` comments,
+/// for the passes over events that no longer have byte ranges.
+pub(crate) fn off_events<'e, 'a: 'e, E>(
+    events: &'e [E],
+    event: impl Fn(&'e E) -> &'e Event<'a>,
+) -> Vec<bool> {
+    let mut off = false;
+    events
+        .iter()
+        .map(|e| {
+            if let Event::Html(html) = event(e) {
+                match switch(html) {
+                    Some(false) if !off => {
+                        off = true;
+                        return false;
                     }
+                    Some(true) => off = false,
+                    _ => {}
                 }
             }
+            off
+        })
+        .collect()
+}
+
+/// Whether the byte at `offset` is somewhere classy has been turned off.
+pub(crate) fn is_off(off_ranges: &[Range<usize>], offset: usize) -> bool {
+    off_ranges.iter().any(|range| range.contains(&offset))
+}
+
+/// Find paragraphs beginning with the class annotator `{:.class-name}`, and the blocks they apply to.
+/// Annotations that fail to parse are reported as diagnostics and left in place, and those
+/// between `<!-- classy:off -->` and `<!-- classy:on -->` are ignored.
+pub(crate) fn scan(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+) -> (Vec<ClassAnnotation>, Vec<Diagnostic>) {
+    let mut class_annotations = vec![];
+    let mut diagnostics = vec![];
+    let off = off_ranges(events);
+    for (i, (event, range)) in events.iter().enumerate() {
+        if !matches!(event, Event::Start(Tag::Paragraph)) || is_off(&off, range.start) {
+            continue;
+        }
+        let first_line = content[range.clone()].lines().next().unwrap_or("");
+        if !annotation::is_annotation(first_line) {
+            if let Some(span) = trailing_annotation(content, range.clone()) {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        diagnostic::TRAILING_ANNOTATION,
+                        "annotations after a paragraph are not applied",
+                        content,
+                        span,
+                    )
+                    .with_fix(
+                        "move the annotation above the paragraph, or run `mdbook-classy fmt`",
+                    ),
+                );
+            }
+            continue;
+        }
+        let text = first_line.trim_end();
+        let span = range.start..range.start + text.len();
+
+        let annotation = match annotation::parse(text) {
+            Ok(annotation) => annotation,
+            Err(e) => {
+                let mut malformed = Diagnostic::error(
+                    diagnostic::MALFORMED_ANNOTATION,
+                    e.message,
+                    content,
+                    span.start + e.offset..span.end,
+                );
+                malformed.fix = e.fix;
+                diagnostics.push(malformed);
+                continue;
+            }
+        };
+        for (name, value) in &annotation.attributes {
+            if let Some(reason) = annotation::unsafe_attribute(name, value) {
+                diagnostics.push(
+                    Diagnostic::error(
+                        diagnostic::UNSAFE_ATTRIBUTE,
+                        format!("unsafe attribute `{}`: {}", name, reason),
+                        content,
+                        span.clone(),
+                    )
+                    .with_fix(format!("remove `{}=...`", name)),
+                );
+            }
+        }
+
+        // Paragraphs don't nest, so the next paragraph end closes this one.
+        let paragraph_end = events[i..]
+            .iter()
+            .position(|(event, _)| matches!(event, Event::End(Tag::Paragraph)))
+            .map_or(events.len() - 1, |offset| i + offset);
+        // Everything on the annotation line, including the line break that ends it, is dropped.
+        let next_line = range.start + first_line.len() + 1;
+        let body_start = (i + 1..paragraph_end)
+            .find(|&j| events[j].1.start >= next_line)
+            .unwrap_or(paragraph_end);
+        let mut class_annotation = ClassAnnotation {
+            annotation,
+            span: span.clone(),
+            block: BlockKind::Paragraph,
+            start: i,
+            body_start,
+            end: paragraph_end,
+        };
+        if body_start == paragraph_end {
+            if let Some((block, end)) = next_block(content, events, span.end, paragraph_end) {
+                class_annotation.block = block;
+                class_annotation.body_start = paragraph_end + 1;
+                class_annotation.end = end;
+            } else if detached_gap(content, events, span.end, paragraph_end).is_some() {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        diagnostic::DETACHED_ANNOTATION,
+                        "a blank line separates this annotation from its block",
+                        content,
+                        span,
+                    )
+                    .with_fix("remove the blank line, or run `mdbook-classy fmt`"),
+                );
+            }
+        }
+        class_annotations.push(class_annotation);
+    }
+    (class_annotations, diagnostics)
+}
+
+/// If an annotation-only paragraph is directly followed by a block, return the block's kind and
+/// the index of the event that ends it.
+fn next_block(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    annotation_end: usize,
+    paragraph_end: usize,
+) -> Option<(BlockKind, usize)> {
+    let (block, next) = match events.get(paragraph_end + 1) {
+        Some((Event::Start(tag), next)) => (BlockKind::of(tag)?, next),
+        _ => return None,
+    };
+    // A blank line in between means the annotation is detached, except for tables: they can't
+    // interrupt a paragraph, so they need exactly one.
+    if next.start < annotation_end {
+        return None;
+    }
+    let gap = &content[annotation_end..next.start];
+    let newlines = gap.matches('\n').count();
+    let blank = gap.chars().all(|c| c.is_whitespace() || c == '>');
+    let attached = match block {
+        BlockKind::Table => blank && newlines == 2,
+        _ => newlines <= 1,
+    };
+    if !attached {
+        return None;
+    }
+
+    let mut depth = 0;
+    for (j, (event, _)) in events.iter().enumerate().skip(paragraph_end + 1) {
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
             _ => {}
         }
+        if depth == 0 {
+            return Some((block, j));
+        }
     }
+    None
+}
 
-    // 3. Construct a new_events vector with <div class="class-name">\n \n</div> around the annotated paragraphs
-    // (and with the class annotation removed).
-    let mut slices = vec![];
+/// If an annotation-only paragraph is followed by blank lines and then a block, return the range
+/// from the end of the annotation to the start of that block's line.
+pub(crate) fn detached_gap(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    annotation_end: usize,
+    paragraph_end: usize,
+) -> Option<Range<usize>> {
+    let next = match events.get(paragraph_end + 1) {
+        Some((Event::Start(Tag::Table(_)), _)) => return None,
+        Some((Event::Start(tag), next)) if BlockKind::of(tag).is_some() => next,
+        _ => return None,
+    };
+    let next_line_start = content[..next.start].rfind('\n').map_or(0, |i| i + 1);
+    let next_first_line = content[next.clone()].lines().next().unwrap_or("");
+    if next_line_start < annotation_end || annotation::is_annotation(next_first_line) {
+        return None;
+    }
+
+    // Only whitespace and blockquote markers may separate the two.
+    let gap = &content[annotation_end..next_line_start];
+    let blank = gap.chars().all(|c| c.is_whitespace() || c == '>');
+    (blank && gap.matches('\n').count() > 1).then_some(annotation_end..next_line_start)
+}
+
+/// If the last line of a multi-line paragraph is an annotation (kramdown's trailing block IAL),
+/// return the range of the annotation text.
+pub(crate) fn trailing_annotation(content: &str, paragraph: Range<usize>) -> Option<Range<usize>> {
+    let source = content[paragraph.clone()].trim_end();
+    let last_line_start = source.rfind('\n')? + 1;
+    let last_line = &source[last_line_start..];
+    let text = last_line.trim_start_matches(|c: char| c.is_whitespace() || c == '>');
+    if !annotation::is_annotation(text) || annotation::parse(text).is_err() {
+        return None;
+    }
+    let start = paragraph.start + source.len() - text.len();
+    Some(start..paragraph.start + source.len())
+}
+
+/// Mutation: the payload here is that it edits chapter.content.
+fn classy(
+    chapter: &mut Chapter,
+    data: &BookData,
+    options: &Options,
+    strategy: Strategy,
+) -> Result<Vec<Diagnostic>, ClassyError> {
+    let info = ChapterInfo {
+        name: chapter.name.clone(),
+        path: chapter.source_path.clone(),
+        number: chapter.number.as_ref().map(ToString::to_string),
+    };
+    let output = transform_chapter(&chapter.content, Some(&info), Some(data), options, strategy)?;
+    chapter.content = output.content;
+    Ok(output.diagnostics)
+}
+
+/// The result of [`transform`]: the new markdown, and any problems found with the annotations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Output {
+    pub content: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// One step in rebuilding a chapter's events with its annotated blocks wrapped in divs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "step", rename_all = "kebab-case")]
+pub enum Splice {
+    /// Copy this range of the incoming events.
+    Copy {
+        events: Range<usize>,
+    },
+    Html {
+        html: String,
+    },
+    /// Two line breaks, which render as a blank line. Line breaks (rather than a newline inside
+    /// the html) keep any blockquote or list prefix on that line.
+    BlankLine,
+}
+
+/// Plan how to splice divs (or the configured wrapper) around the annotated blocks, leaving the
+/// annotations out. When stripping, the blocks are left unwrapped.
+///
+/// Blocks with a `replacement` from a handler are replaced by it instead. Container fences are
+/// replaced by the html in the `layout`, or dropped when stripping.
+pub(crate) fn splice_plan(
+    class_annotations: &[ClassAnnotation],
+    replacements: &[Option<String>],
+    layout: &Layout,
+    events: &[Event],
+    options: &Options,
+    strategy: Strategy,
+) -> Vec<Splice> {
+    let mut plan = vec![];
+    let copy = |plan: &mut Vec<Splice>, events: Range<usize>| {
+        if !events.is_empty() {
+            plan.push(Splice::Copy { events });
+        }
+    };
+    let fence = |plan: &mut Vec<Splice>, last_end: &mut usize, fence: &Fence| {
+        // Fences inside a block already copied are left as they are.
+        if fence.events.start < *last_end {
+            return;
+        }
+        copy(plan, *last_end..fence.events.start);
+        *last_end = fence.events.end;
+        if strategy == Strategy::Wrap {
+            plan.push(Splice::Html {
+                html: fence.html.clone(),
+            });
+            if !matches!(events.get(*last_end), None | Some(Event::End(_))) {
+                plan.push(Splice::BlankLine);
+            }
+        }
+    };
+    let mut fences = layout.fences.iter().peekable();
     let mut last_end = 0;
-    let div_starts: Vec<Event> = class_annotations
-        .iter()
-        .map(|ca| Event::Html(CowStr::from(format!("<div class=\"{}\">", ca.class))))
-        .collect();
-    let div_end: Vec<Event> = vec![Event::Html(CowStr::from("</div>"))];
     for (i, ca) in class_annotations.iter().enumerate() {
+        while let Some(next) = fences.next_if(|next| next.events.start < ca.start) {
+            fence(&mut plan, &mut last_end, next);
+        }
+
         // Add unclassed events.
-        slices.push(&incoming_events[last_end..ca.paragraph_start]);
+        copy(&mut plan, last_end..ca.start);
+        last_end = ca.end + 1;
+
+        if let Some(replacement) = replacements.get(i).and_then(Option::as_ref) {
+            if !replacement.trim().is_empty() {
+                plan.push(Splice::Html {
+                    html: replacement.trim_end().to_string(),
+                });
+                if !matches!(events.get(last_end), None | Some(Event::End(_))) {
+                    plan.push(Splice::BlankLine);
+                }
+            }
+            continue;
+        }
+
+        if strategy == Strategy::Strip {
+            // Drop paragraphs that held nothing but the annotation.
+            if ca.block == BlockKind::Paragraph && ca.body_start < ca.end {
+                copy(&mut plan, ca.start..ca.start + 1);
+            }
+            if ca.body_start < ca.end || ca.block != BlockKind::Paragraph {
+                copy(&mut plan, ca.body_start..last_end);
+            }
+            continue;
+        }
+
+        // Add <div class="class-name">, and a blank line so the block still renders as markdown.
+        // Collapsible blocks become <details>, which may take its <summary> from a heading.
+        let wrapper = &options.wrapper;
+        let collapsible = ca
+            .annotation
+            .classes
+            .iter()
+            .any(|class| options.collapsible.contains(class));
+        let mut summary_heading = None;
+        let (open, close) = if let Some(wrapping) = layout.wrappers.get(&i) {
+            wrapping.clone()
+        } else if collapsible {
+            let (summary, heading) =
+                details::summary(&ca.annotation, events, ca.body_start..last_end);
+            summary_heading = heading;
+            (
+                details::open(&ca.annotation, &summary),
+                "</details>".to_string(),
+            )
+        } else {
+            let admonition = if options.admonitions {
+                admonition::open(&ca.annotation, wrapper)
+            } else {
+                None
+            };
+            (
+                admonition
+                    .unwrap_or_else(|| format!("<{}{}>", wrapper, ca.annotation.html_attributes())),
+                format!("</{}>", wrapper),
+            )
+        };
+        let open = match layout.labels.get(&i) {
+            Some(label) => format!("{}\n<p class=\"classy-label\">{}</p>", open, escape(label)),
+            None => open,
+        };
+        plan.push(Splice::Html { html: open });
+        plan.push(Splice::BlankLine);
+
+        // Add paragraph opener, unless the annotation was alone in its paragraph.
+        if ca.block == BlockKind::Paragraph {
+            copy(&mut plan, ca.start..ca.start + 1);
+        }
+
+        // Add the rest of the block, skipping the class annotation and any heading summarized.
+        match summary_heading {
+            Some(heading) => {
+                copy(&mut plan, ca.body_start..heading.start);
+                copy(&mut plan, heading.end..last_end);
+            }
+            None => copy(&mut plan, ca.body_start..last_end),
+        }
+
+        // Add </div>, and a blank line so whatever follows isn't swallowed by the html block.
+        plan.push(Splice::Html { html: close });
+        if !matches!(events.get(last_end), None | Some(Event::End(_))) {
+            plan.push(Splice::BlankLine);
+        }
+    }
+    for next in fences {
+        fence(&mut plan, &mut last_end, next);
+    }
+    copy(&mut plan, last_end..events.len());
+    plan
+}
+
+/// This is where the markdown transformation actually happens.
+/// Take paragraphs beginning with `{:.class-name}` and give them special rendering, returning the
+/// new markdown and any problems found with the annotations.
+///
+/// This is everything the preprocessor does to a chapter for the html renderer, without needing
+/// an mdbook `Book`.
+pub fn transform(content: &str, options: &Options) -> Result<Output, ClassyError> {
+    transform_with(content, options, Strategy::Wrap)
+}
 
-        last_end = ca.paragraph_end.unwrap_or(incoming_events.len() - 1);
+/// Like [`transform`], handling the annotations with the given strategy.
+pub fn transform_with(
+    content: &str,
+    options: &Options,
+    strategy: Strategy,
+) -> Result<Output, ClassyError> {
+    transform_chapter(&exclude(content, options), None, None, options, strategy)
+}
+
+/// What chapters need to know about the rest of the book: the numbered blocks `[@id]` can refer
+/// to, the blocks `{{#classy-index class}}` lists, and the glossary terms.
+#[derive(Debug, Default)]
+pub(crate) struct BookData {
+    pub references: References,
+    pub index: Index,
+    pub glossary: Glossary,
+}
+
+impl BookData {
+    /// Collect the numbered and indexed blocks in every chapter of `book`.
+    pub fn from_book(book: &Book, options: &Options) -> BookData {
+        let mut data = BookData::default();
+        let contents: Vec<(&Chapter, String, Options)> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some(chapter),
+                _ => None,
+            })
+            .filter_map(|chapter| {
+                let (content, front_matter, _) = front_matter::split(&chapter.content);
+                match front_matter {
+                    Some(front_matter) if !front_matter.classy => None,
+                    Some(front_matter) => Some((chapter, content, front_matter.apply(options))),
+                    None => Some((chapter, content, options.clone())),
+                }
+            })
+            .collect();
+        let chapters: Vec<_> = contents
+            .iter()
+            .map(|(chapter, content, options)| {
+                let events: Vec<_> = new_cmark_parser(content, false)
+                    .into_offset_iter()
+                    .collect();
+                (*chapter, content, options, events)
+            })
+            .collect();
+        // Blocks only get ids to link to once we know which classes are listed anywhere.
+        for (_, _, _, events) in &chapters {
+            data.index.classes.extend(
+                index::directives(events, |(event, _)| event)
+                    .into_iter()
+                    .map(|(_, class)| class),
+            );
+        }
+        for (chapter, content, options, events) in &chapters {
+            let (class_annotations, _) = scan_with_options(content, events, options);
+            let info = ChapterInfo {
+                name: chapter.name.clone(),
+                path: chapter.source_path.clone(),
+                number: chapter.number.as_ref().map(ToString::to_string),
+            };
+            data.add(events, class_annotations, &info, options);
+        }
+        data
+    }
+
+    /// Collect the numbered and indexed blocks of a chapter on its own.
+    fn from_chapter(
+        events: &[(Event, Range<usize>)],
+        class_annotations: &[ClassAnnotation],
+        chapter: Option<&ChapterInfo>,
+        options: &Options,
+    ) -> BookData {
+        let mut data = BookData::default();
+        data.index.classes = index::directives(events, |(event, _)| event)
+            .into_iter()
+            .map(|(_, class)| class)
+            .collect();
+        let info = chapter.cloned().unwrap_or_default();
+        data.add(events, class_annotations.to_vec(), &info, options);
+        data
+    }
+
+    fn add(
+        &mut self,
+        events: &[(Event, Range<usize>)],
+        mut class_annotations: Vec<ClassAnnotation>,
+        chapter: &ChapterInfo,
+        options: &Options,
+    ) {
+        self.assign_ids(events, &mut class_annotations);
+        let labels = numbering::labels(&class_annotations, chapter.number.as_deref(), options);
+        self.references
+            .add(&class_annotations, &labels, chapter.path.as_deref());
+        self.index.add(events, &class_annotations, chapter);
+        self.glossary.add(events, &class_annotations, chapter);
+    }
+
+    /// Give the blocks that indexes and the glossary link to ids, if they don't have them.
+    fn assign_ids(
+        &self,
+        events: &[(Event, Range<usize>)],
+        class_annotations: &mut [ClassAnnotation],
+    ) {
+        glossary::assign_ids(events, class_annotations);
+        self.index.assign_ids(class_annotations);
+    }
+}
+
+/// Like [`transform_with`], telling handlers which chapter `content` is, and resolving `[@id]`
+/// references and indexes with the blocks in the rest of the book too.
+pub(crate) fn transform_chapter(
+    content: &str,
+    chapter: Option<&ChapterInfo>,
+    data: Option<&BookData>,
+    options: &Options,
+    strategy: Strategy,
+) -> Result<Output, ClassyError> {
+    trace_chapter(content, chapter, data, options, strategy).map(|(output, _)| output)
+}
+
+/// What [`transform_chapter`] worked from, for explaining it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Trace {
+    /// The markdown the events were parsed from, without its front matter.
+    pub content: String,
+    /// The annotations found, with the ids they were given.
+    pub class_annotations: Vec<ClassAnnotation>,
+    pub plan: Vec<Splice>,
+}
+
+/// Like [`transform_chapter`], also returning what it did along the way.
+pub(crate) fn trace_chapter(
+    content: &str,
+    chapter: Option<&ChapterInfo>,
+    data: Option<&BookData>,
+    options: &Options,
+    strategy: Strategy,
+) -> Result<(Output, Trace), ClassyError> {
+    if !valid_tag(&options.wrapper) {
+        return Err(ClassyError::InvalidOption(format!(
+            "`wrapper` must be an html tag name, found `{}`",
+            options.wrapper
+        )));
+    }
+
+    // Front matter can set the chapter's own options, or turn classy off for it.
+    let (content, front_matter, mut diagnostics) = front_matter::split(content);
+    let content = content.as_str();
+    let local_options;
+    let options = match &front_matter {
+        Some(front_matter) if !front_matter.classy => {
+            let events = new_cmark_parser(content, false).count();
+            let trace = Trace {
+                content: content.to_string(),
+                class_annotations: vec![],
+                plan: vec![Splice::Copy { events: 0..events }],
+            };
+            return Ok((
+                Output {
+                    content: content.to_string(),
+                    diagnostics,
+                },
+                trace,
+            ));
+        }
+        Some(front_matter) => {
+            local_options = front_matter.apply(options);
+            &local_options
+        }
+        None => options,
+    };
+    let chapter_classes = front_matter
+        .as_ref()
+        .map(FrontMatter::classes)
+        .unwrap_or_default();
+
+    // 1. Parse the inbound markdown into an Event vector.
+    let incoming: Vec<(Event, Range<usize>)> = new_cmark_parser(content, false)
+        .into_offset_iter()
+        .collect();
+
+    // 2. Find paragraphs beginning with the class annotator `{:.class-name}` and record their information in
+    // a vector of ClassAnnotation structs.
+    let (mut class_annotations, scan_diagnostics) = scan_with_options(content, &incoming, options);
+    diagnostics.extend(scan_diagnostics);
+    let (containers, container_diagnostics) =
+        scan_containers(content, &incoming, &class_annotations, options);
+    diagnostics.extend(container_diagnostics);
+    let path = chapter.and_then(|chapter| chapter.path.as_deref());
+    let (chapter_annotation, chapter_diagnostics) =
+        chapter_annotation(content, &incoming, path, &chapter_classes, options)?;
+    diagnostics.extend(chapter_diagnostics);
+    let local;
+    let data = match data {
+        Some(data) => data,
+        None => {
+            local = BookData::from_chapter(&incoming, &class_annotations, chapter, options);
+            &local
+        }
+    };
+    data.assign_ids(&incoming, &mut class_annotations);
+    let labels = numbering::labels(
+        &class_annotations,
+        chapter.and_then(|chapter| chapter.number.as_deref()),
+        options,
+    );
+    if !options.numbered.is_empty() {
+        diagnostics.extend(undefined_references(content, &incoming, |id| {
+            data.references.link(id, path).is_some()
+        }));
+    }
+    for (range, class) in index::directives(&incoming, |(event, _)| event) {
+        if !data.index.entries.iter().any(|entry| entry.class == class) {
+            let span = incoming[range.start].1.clone();
+            diagnostics.push(
+                Diagnostic::warning(
+                    diagnostic::EMPTY_INDEX,
+                    format!("no block has the class `{}`, so its index is empty", class),
+                    content,
+                    span.start..span.start + content[span].trim_end().len(),
+                )
+                .with_fix(format!(
+                    "annotate some blocks with `{{:.{}}}`, or remove the index",
+                    class
+                )),
+            );
+        }
+    }
+    if data.glossary.terms.is_empty() {
+        for (range, ()) in glossary::directives(&incoming, |(event, _)| event) {
+            let span = incoming[range.start].1.clone();
+            diagnostics.push(
+                Diagnostic::warning(
+                    diagnostic::EMPTY_INDEX,
+                    "no block has the class `term`, so the glossary is empty",
+                    content,
+                    span.start..span.start + content[span].trim_end().len(),
+                )
+                .with_fix("define some terms with `{:.term name=\"...\"}`, or remove the glossary"),
+            );
+        }
+    }
+    if options.strict && diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return Err(ClassyError::Strict(diagnostics));
+    }
+    let replacements = handle(content, chapter, &incoming, &class_annotations, options)?;
+    let incoming_events: Vec<Event> = incoming.into_iter().map(|(event, _)| event).collect();
 
-        let paragraph = &incoming_events[ca.paragraph_start..(last_end + 1)];
+    // 3. Construct a new_events vector with <div class="class-name">\n \n</div> around the annotated blocks
+    // (and with the class annotation removed).
+    let mut new_events = Vec::with_capacity(incoming_events.len());
+    let mut layout = layout(&containers, &class_annotations, options);
+    layout.labels = labels
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, label)| Some((i, label?)))
+        .collect();
+    // The whole chapter is wrapped in an element with its classes, opened in place of any
+    // `{::chapter}` paragraph.
+    let wrap_chapter =
+        strategy == Strategy::Wrap && chapter_annotation.annotation != Annotation::default();
+    let chapter_open = format!(
+        "<{}{}>",
+        options.wrapper,
+        chapter_annotation.annotation.html_attributes()
+    );
+    if let Some(events) = chapter_annotation.directive.clone() {
+        layout.fences.insert(
+            0,
+            Fence {
+                events,
+                html: if wrap_chapter {
+                    chapter_open.clone()
+                } else {
+                    String::new()
+                },
+            },
+        );
+    }
+    let mut plan = splice_plan(
+        &class_annotations,
+        &replacements,
+        &layout,
+        &incoming_events,
+        options,
+        strategy,
+    );
+    if wrap_chapter && chapter_annotation.directive.is_none() {
+        plan.splice(
+            0..0,
+            [Splice::Html { html: chapter_open }, Splice::BlankLine],
+        );
+    }
+    for splice in plan.iter().cloned() {
+        match splice {
+            Splice::Copy { events } => new_events.extend_from_slice(&incoming_events[events]),
+            Splice::Html { html } => {
+                // Line breaks between the lines keep any blockquote or list prefix on each.
+                for (i, line) in html.lines().enumerate() {
+                    if i > 0 {
+                        new_events.push(Event::SoftBreak);
+                    }
+                    new_events.push(Event::Html(CowStr::from(line.to_string())));
+                }
+            }
+            Splice::BlankLine => new_events.extend([Event::SoftBreak, Event::SoftBreak]),
+        }
+    }
 
-        // Add <div class="class-name">
-        slices.push(&div_starts[i..i + 1]);
+    if !options.numbered.is_empty() {
+        new_events = link_references(new_events, |id| data.references.link(id, path));
+    }
+    if options.link_terms {
+        new_events = data.glossary.link_terms(new_events, path);
+    }
+    if !data.index.classes.is_empty() {
+        new_events = expand_directives(new_events, &data.index, path);
+    }
+    let glossaries = glossary::directives(&new_events, |event| event);
+    if !glossaries.is_empty() {
+        new_events = index::replace(new_events, glossaries, |()| data.glossary.list(path));
+    } else if path.is_some() && path == options.glossary_chapter.as_deref() {
+        new_events.extend(data.glossary.list(path));
+    }
+    if wrap_chapter {
+        if let Some(Event::Html(_)) = new_events.last() {
+            new_events.push(Event::SoftBreak);
+        }
+        new_events.push(Event::Html(CowStr::from(format!("</{}>", options.wrapper))));
+    }
 
-        // Add paragraph opener.
-        slices.push(&paragraph[0..1]);
+    // 4. Generate markdown from the new event vector.
+    let mut buf = String::with_capacity(content.len() + 128);
+    pulldown_cmark_to_cmark::cmark(new_events.into_iter(), &mut buf)?;
+    let trace = Trace {
+        content: content.to_string(),
+        class_annotations,
+        plan,
+    };
+    Ok((
+        Output {
+            content: buf,
+            diagnostics,
+        },
+        trace,
+    ))
+}
 
-        // Add the rest of the paragraph, skipping the class annotation.
-        slices.push(&paragraph[2..]);
+/// Ask the handlers registered for the annotated classes what to replace each block with.
+pub(crate) fn handle(
+    content: &str,
+    chapter: Option<&ChapterInfo>,
+    events: &[(Event, Range<usize>)],
+    class_annotations: &[ClassAnnotation],
+    options: &Options,
+) -> Result<Vec<Option<String>>, ClassyError> {
+    let mut replacements = vec![];
+    for ca in class_annotations {
+        let found = ca
+            .annotation
+            .classes
+            .iter()
+            .find_map(|class| Some((class.as_str(), options.handler(class)?)));
+        let (class, handler) = match found {
+            Some(found) => found,
+            None => {
+                replacements.push(None);
+                continue;
+            }
+        };
+        let mut body = vec![];
+        if ca.block == BlockKind::Paragraph {
+            body.push(events[ca.start].0.clone());
+        }
+        body.extend(
+            events[ca.body_start..=ca.end]
+                .iter()
+                .map(|(event, _)| event.clone()),
+        );
+        let markdown = if ca.body_start < ca.end || ca.block != BlockKind::Paragraph {
+            // Take off the blockquote markers or indentation the annotation's line has.
+            let line_start = content[..ca.span.start].rfind('\n').map_or(0, |i| i + 1);
+            let prefix = &content[line_start..ca.span.start];
+            content[events[ca.body_start].1.start..events[ca.end].1.end]
+                .lines()
+                .map(|line| {
+                    line.strip_prefix(prefix)
+                        .or_else(|| line.strip_prefix(prefix.trim_end()))
+                        .unwrap_or(line)
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            String::new()
+        };
+        let block = Block {
+            class,
+            annotation: &ca.annotation,
+            kind: ca.block,
+            markdown: markdown.trim_end(),
+            events: &body,
+            chapter,
+        };
+        let replacement = handler
+            .handle(&block)
+            .map_err(|source| ClassyError::Handler {
+                class: class.to_string(),
+                source,
+            })?;
+        replacements.push(replacement);
+    }
+    Ok(replacements)
+}
 
-        // Add </div>.
-        slices.push(&div_end[..]);
+/// Scan for annotations, then resolve their aliases and drop attributes that aren't allowed.
+pub(crate) fn scan_with_options(
+    content: &str,
+    events: &[(Event, Range<usize>)],
+    options: &Options,
+) -> (Vec<ClassAnnotation>, Vec<Diagnostic>) {
+    let (mut class_annotations, mut diagnostics) = scan(content, events);
+    for ca in &mut class_annotations {
+        ca.annotation.classes = options.resolve_aliases(&ca.annotation.classes);
+        if let Some(allowed) = &options.allowed_attributes {
+            for (name, _) in &ca.annotation.attributes {
+                if !allowed.contains(name) {
+                    diagnostics.push(
+                        Diagnostic::warning(
+                            diagnostic::DISALLOWED_ATTRIBUTE,
+                            format!("attribute `{}` is not in `allowed-attributes`", name),
+                            content,
+                            ca.span.clone(),
+                        )
+                        .with_fix(format!("remove `{}=...`, or allow it", name)),
+                    );
+                }
+            }
+            ca.annotation
+                .attributes
+                .retain(|(name, _)| allowed.contains(name));
+        }
     }
-    slices.push(&incoming_events[last_end..]);
-    let new_events = slices.concat();
+    (class_annotations, diagnostics)
+}
 
-    // 4. Update chapter.content using markdown generated from the new event vector.
-    let mut buf = String::with_capacity(chapter.content.len() + 128);
-    pulldown_cmark_to_cmark::cmark(new_events.into_iter(), &mut buf)
-        .expect("unable to re-render cmark");
-    chapter.content = buf;
-    Ok(())
+fn valid_tag(tag: &str) -> bool {
+    tag.starts_with(|c: char| c.is_ascii_alphabetic())
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::handler::HandlerError;
+    use mdbook::BookItem;
     use serde_json::json;
+    use std::collections::BTreeMap;
 
     macro_rules! assert_round_trip_fail {
         ($input_content:ident, $expected_incorrect_output_content:ident) => {
@@ -211,6 +1278,475 @@ red text
         assert_eq!(Classy::new().run(&ctx, book).unwrap(), expected_book)
     }
 
+    #[test]
+    fn attributes_and_nested_paragraphs() {
+        let content = "> {:.note #first title=\"A & B\"}  \n> quoted text";
+        let expected_content =
+            "\n > \n > <div id=\"first\" class=\"note\" title=\"A &amp; B\">\n > \n > quoted text\n > \n > </div>";
+        let ctx = mock_context();
+        let book = mock_book(content);
+        let expected_book = mock_book(expected_content);
+
+        assert_eq!(Classy::new().run(&ctx, book).unwrap(), expected_book)
+    }
+
+    #[test]
+    fn malformed_annotation_left_in_place() {
+        let content = "{:.red\" onclick=\"alert(1)}\nred text";
+        let events: Vec<_> = new_cmark_parser(content, false)
+            .into_offset_iter()
+            .collect();
+        let (class_annotations, diagnostics) = scan(content, &events);
+        assert!(class_annotations.is_empty());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].line, diagnostics[0].column), (1, 3));
+    }
+
+    #[test]
+    fn annotations_apply_to_the_next_block() {
+        let content =
+            "{:.a}\n- one\n- two\n\n{:.b}\n```\ncode\n```\n\n{:.c}\n\n| x |\n|---|\n\nafter\n";
+        let Output {
+            content: output,
+            diagnostics,
+        } = transform(content, &Options::default()).unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(
+            output,
+            "<div class=\"a\">\n\n* one\n* two\n\n</div>\n\n\
+             <div class=\"b\">\n\n\n````\ncode\n````\n\n</div>\n\n\
+             <div class=\"c\">\n\n|x|\n|-|\n\n</div>\n\nafter"
+        );
+    }
+
+    #[test]
+    fn strict_mode_fails_on_errors() {
+        let options = Options {
+            strict: true,
+            ..Options::default()
+        };
+        assert!(transform("{:.a}\ntext", &options).is_ok());
+        let error = transform("{:.a\ntext", &options).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1 annotation error\n\
+             1:5: error[malformed-annotation]: unclosed annotation, expected `}` (fix: add a closing `}`)"
+        );
+        assert!(transform("{:.a\ntext", &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn builder_options_under_book_config() {
+        let classy = Classy::builder()
+            .wrapper("section")
+            .alias("tip", "callout tip")
+            .allowed_attributes(["title"])
+            .build();
+        let content = "{:.tip title=\"Hi\" lang=\"en\"}\ntext";
+
+        let book = classy.run(&mock_context(), mock_book(content)).unwrap();
+        assert_eq!(
+            book,
+            mock_book("<section class=\"callout tip\" title=\"Hi\">\n\ntext\n\n</section>")
+        );
+
+        let mut ctx = mock_context();
+        ctx.config
+            .set("preprocessor.classy.wrapper", "aside")
+            .unwrap();
+        let book = classy.run(&ctx, mock_book(content)).unwrap();
+        assert_eq!(
+            book,
+            mock_book("<aside class=\"callout tip\" title=\"Hi\">\n\ntext\n\n</aside>")
+        );
+
+        assert!(classy.supports_renderer("html"));
+        assert!(!classy.supports_renderer("markdown"));
+        ctx.renderer = "markdown".to_string();
+        let book = classy.run(&ctx, mock_book(content)).unwrap();
+        assert_eq!(book, mock_book(content));
+    }
+
+    #[test]
+    fn disallowed_attributes_are_reported() {
+        let options = Options {
+            allowed_attributes: Some(vec![]),
+            ..Options::default()
+        };
+        let output = transform("{:.a lang=en}\ntext", &options).unwrap();
+        assert_eq!(output.content, "<div class=\"a\">\n\ntext\n\n</div>");
+        assert_eq!(output.diagnostics[0].rule, diagnostic::DISALLOWED_ATTRIBUTE);
+
+        let options = Options {
+            wrapper: "not a tag".to_string(),
+            ..Options::default()
+        };
+        assert!(matches!(
+            transform("text", &options),
+            Err(ClassyError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn strip_strategy_removes_annotations() {
+        let content = "{:.a}\nfirst\n\n{:.b}\n- item\n\n{:.c}\n\nlast\n";
+        let output = transform_with(content, &Options::default(), Strategy::Strip).unwrap();
+        assert_eq!(output.content, "first\n\n* item\n\nlast");
+    }
+
+    #[test]
+    fn admonitions_are_opt_in() {
+        let content = "> {:.note title=\"Read me\"}\n> Some *text*.\n\nMore.\n";
+        let output = transform(content, &Options::default()).unwrap();
+        assert!(output
+            .content
+            .contains("> <div class=\"note\" title=\"Read me\">"));
+
+        let options = Options {
+            admonitions: true,
+            ..Options::default()
+        };
+        let output = transform(content, &options).unwrap();
+        assert_eq!(
+            output.content,
+            "\n > \n > <div class=\"admonition note\" role=\"note\">\n > <p class=\"admonition-title\"><i class=\"fa fa-pencil\" aria-hidden=\"true\"></i> Read me</p>\n > \n > Some *text*.\n > \n > </div>\n\nMore."
+        );
+    }
+
+    #[test]
+    fn collapsible_blocks() {
+        let content = "{:.collapse summary=\"Show solution\"}\nThe answer.\n\n\
+                       {:.spoiler open=true}\n> ## Hint *one*\n> Look.\n";
+        let options = Options {
+            collapsible: vec!["collapse".to_string(), "spoiler".to_string()],
+            ..Options::default()
+        };
+        let output = transform(content, &options).unwrap();
+        let html = mdbook::utils::render_markdown(&output.content, false);
+        assert_eq!(
+            html,
+            "<details class=\"collapse\">\n<summary>Show solution</summary>\n<p>The answer.</p>\n</details>\n\
+             <details class=\"spoiler\" open>\n<summary>Hint <em>one</em></summary>\n\
+             <blockquote>\n<p>Look.</p>\n</blockquote>\n</details>"
+        );
+
+        // Nothing collapses unless it's asked for.
+        let output = transform(content, &Options::default()).unwrap();
+        assert!(!output.content.contains("<details"));
+    }
+
+    #[test]
+    fn containers() {
+        let content = "::: {.aside #a}\n\n{:.note}\nText.\n\n:::\n\n::: tabs\n\n\
+                       {:.tab title=One}\n- one\n\n{:.tab title=Two}\nTwo.\n\n:::\n\n:::\n";
+        let output = transform(content, &Options::default()).unwrap();
+        let html = mdbook::utils::render_markdown(&output.content, false);
+        assert!(html.starts_with(
+            "<div id=\"a\" class=\"aside\">\n<div class=\"note\">\n<p>Text.</p>\n</div>\n</div>\n<style>"
+        ));
+        assert!(html.contains(
+            "tabindex=\"0\">\n<ul>\n<li>one</li>\n</ul>\n</div>\n<div id=\"classy-tabs-1-panel-2\""
+        ));
+        assert!(html.ends_with("hidden>\n<p>Two.</p>\n</div>\n</div>\n<p>:::</p>\n"));
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].rule, diagnostic::UNMATCHED_FENCE);
+
+        let output = transform_with(content, &Options::default(), Strategy::Strip).unwrap();
+        assert_eq!(output.content, "Text.\n\n* one\n\nTwo.\n\n:::");
+
+        // A container fenced inside an annotated block is only emitted once, as part of it.
+        let content = "{:.note}\n> ::: aside\n>\n> text\n>\n> :::\n\nAfter.\n";
+        let output = transform(content, &Options::default()).unwrap();
+        assert_eq!(output.content.matches("text").count(), 1);
+        assert!(!output.content.contains("aside\""));
+        assert!(output.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn numbered_blocks_and_references() {
+        let mut book = mock_book("{:.theorem}\nFirst.\n\n{:.lemma #lem}\nUsed by [@thm].\n");
+        let mut chapter = Chapter::new(
+            "Chapter 2",
+            "{:.theorem #thm}\nSecond, by [@lem] and [@nope].\n".to_string(),
+            "maths/chapter_2.md",
+            vec![],
+        );
+        chapter.number = Some([2].into_iter().collect());
+        book.push_item(chapter);
+
+        let classy = Classy::builder()
+            .options(Options {
+                numbered: BTreeMap::from([
+                    ("theorem".to_string(), "Theorem".to_string()),
+                    ("lemma".to_string(), "Lemma".to_string()),
+                ]),
+                ..Options::default()
+            })
+            .build();
+        let book = classy.run(&mock_context(), book).unwrap();
+        let contents: Vec<_> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some(chapter.content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            contents[0],
+            "<div class=\"theorem\">\n<p class=\"classy-label\">Theorem 1.1</p>\n\nFirst.\n\n</div>\n\n\
+             <div id=\"lem\" class=\"lemma\">\n<p class=\"classy-label\">Lemma 1.1</p>\n\n\
+             Used by [Theorem 2.1](maths/chapter_2.md#thm).\n\n</div>"
+        );
+        assert_eq!(
+            contents[1],
+            "<div id=\"thm\" class=\"theorem\">\n<p class=\"classy-label\">Theorem 2.1</p>\n\n\
+             Second, by [Lemma 1.1](../chapter_1.md#lem) and [@nope].\n\n</div>"
+        );
+    }
+
+    #[test]
+    fn indexes() {
+        let mut book =
+            mock_book("{:.exercise}\nAdd `1` and 2.\nThen 3.\n\n{:.exercise #hard}\nHard.\n");
+        book.push_item(Chapter::new(
+            "Exercises",
+            "{:.exercise title=\"Last\"}\nOne more.\n\n{{#classy-index exercise}}\n".to_string(),
+            "exercises.md",
+            vec![],
+        ));
+        let book = Classy::default().run(&mock_context(), book).unwrap();
+        let contents: Vec<_> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some(chapter.content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            contents[0],
+            "<div id=\"exercise-1\" class=\"exercise\">\n\nAdd `1` and 2.\nThen 3.\n\n</div>\n\n\
+             <div id=\"hard\" class=\"exercise\">\n\nHard.\n\n</div>"
+        );
+        assert_eq!(
+            contents[1],
+            "<div id=\"exercise-1\" class=\"exercise\" title=\"Last\">\n\nOne more.\n\n</div>\n\n\
+             * [Chapter 1](chapter_1.md)\n  * [Add 1 and 2.](chapter_1.md#exercise-1)\n  * [Hard.](chapter_1.md#hard)\n\
+             * [Exercises](exercises.md)\n  * [Last](#exercise-1)"
+        );
+
+        let output = transform(
+            "{:.exercise}\nOnly.\n\n{{#classy-index exercise}}\n\n{{#classy-index quiz}}\n",
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            output.content,
+            "<div id=\"exercise-1\" class=\"exercise\">\n\nOnly.\n\n</div>\n\n* [Only.](#exercise-1)"
+        );
+        assert_eq!(output.diagnostics.len(), 1);
+        assert_eq!(output.diagnostics[0].rule, diagnostic::EMPTY_INDEX);
+    }
+
+    #[test]
+    fn glossary() {
+        let mut book = mock_book(
+            "{:.term name=\"Idempotent\"}\nDoing it twice is\nlike doing it once.\n\n{:.term}\nCache\nA fast store.\n",
+        );
+        book.push_item(Chapter::new(
+            "Glossary",
+            "PUT is idempotent, and idempotent again. A cache is not.\n".to_string(),
+            "glossary.md",
+            vec![],
+        ));
+        let classy = Classy::builder()
+            .options(Options {
+                glossary_chapter: Some(PathBuf::from("glossary.md")),
+                link_terms: true,
+                ..Options::default()
+            })
+            .build();
+        let book = classy.run(&mock_context(), book).unwrap();
+        let contents: Vec<_> = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(chapter) => Some(chapter.content.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            contents[0],
+            "<div id=\"term-idempotent\" class=\"term\" name=\"Idempotent\">\n\n\
+             Doing it twice is\nlike doing it once.\n\n</div>\n\n\
+             <div id=\"term-cache\" class=\"term\">\n\nCache\nA fast store.\n\n</div>"
+        );
+        assert_eq!(
+            contents[1],
+            "PUT is [idempotent](chapter_1.md#term-idempotent), and idempotent again. \
+             A [cache](chapter_1.md#term-cache) is not.\n\n\
+             * **Cache**: A fast store. ([Chapter 1](chapter_1.md#term-cache))\n\
+             * **Idempotent**: Doing it twice is like doing it once. ([Chapter 1](chapter_1.md#term-idempotent))"
+        );
+
+        let output = transform(
+            "{:.term name=API #api}\nAn interface.\n\n{{#classy-glossary}}\n",
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            output.content,
+            "<div id=\"api\" class=\"term\" name=\"API\">\n\nAn interface.\n\n</div>\n\n\
+             * **API**: An interface. ([↩](#api))"
+        );
+        let output = transform("{{#classy-glossary}}\n", &Options::default()).unwrap();
+        assert_eq!(output.content, "");
+        assert_eq!(output.diagnostics[0].rule, diagnostic::EMPTY_INDEX);
+    }
+
+    #[test]
+    fn excluded_classes_go_for_every_renderer() {
+        let classy = Classy::builder()
+            .options(Options {
+                exclude_classes: vec!["internal".to_string()],
+                ..Options::default()
+            })
+            .build();
+        assert!(classy.supports_renderer("pdf"));
+        let mut ctx = mock_context();
+        ctx.renderer = "pdf".to_string();
+        let book = mock_book("{:.internal}\nSecret.\n\n{:.note}\nPublic.\n");
+        let book = classy.run(&ctx, book).unwrap();
+        match &book.sections[0] {
+            BookItem::Chapter(chapter) => {
+                assert_eq!(chapter.content, "\n\n\n{:.note}\nPublic.\n")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn chapter_classes() {
+        let classy = Classy::builder()
+            .options(Options {
+                chapter_classes: BTreeMap::from([("chapter_*.md".to_string(), "wide".to_string())]),
+                ..Options::default()
+            })
+            .build();
+        let book = mock_book("{::chapter .appendix}\n\n# Appendix\n\nText.\n");
+        let book = classy.run(&mock_context(), book).unwrap();
+        match &book.sections[0] {
+            BookItem::Chapter(chapter) => assert_eq!(
+                chapter.content,
+                "<div class=\"wide appendix\">\n\n# Appendix\n\nText.\n\n</div>"
+            ),
+            _ => unreachable!(),
+        }
+
+        let output = transform_with(
+            "{::chapter .appendix}\n\nText.\n",
+            &Options::default(),
+            Strategy::Strip,
+        )
+        .unwrap();
+        assert_eq!(output.content, "Text.");
+    }
+
+    #[test]
+    fn front_matter() {
+        let content =
+            "+++\nclasses = \"appendix\"\n[aliases]\ntip = \"note\"\n+++\n\n{:.tip}\nText.\n";
+        let output = transform(content, &Options::default()).unwrap();
+        assert_eq!(
+            output.content,
+            "<div class=\"appendix\">\n\n<div class=\"note\">\n\nText.\n\n</div>\n</div>"
+        );
+
+        let content = "+++\nclassy = false\n+++\n{:.tip}\nText.\n";
+        let output = transform(content, &Options::default()).unwrap();
+        assert_eq!(output.content, "\n\n\n{:.tip}\nText.\n");
+
+        let content = "+++\nstrict = true\n+++\n{:.tip\nText.\n";
+        assert!(matches!(
+            transform(content, &Options::default()),
+            Err(ClassyError::Strict(_))
+        ));
+    }
+
+    #[test]
+    fn classy_off_and_on() {
+        let content = "<!-- classy:off -->\n\n{:.a}\nLiteral.\n\n{:.b\n\n<!--classy:on-->\n\n{:.c}\nStyled.\n\n<!-- classy:off -->\n\n{:.d}\nTo the end.\n";
+        let output = transform(content, &Options::default()).unwrap();
+        assert_eq!(
+            output.content,
+            "<!-- classy:off -->\n\n{:.a}\nLiteral.\n\n{:.b\n\n<!--classy:on-->\n<div class=\"c\">\n\nStyled.\n\n</div>\n\n\
+             <!-- classy:off -->\n\n{:.d}\nTo the end."
+        );
+        assert!(output.diagnostics.is_empty());
+
+        // References, indexes and glossary terms are left alone too.
+        let options = Options {
+            numbered: BTreeMap::from([("theorem".to_string(), "Theorem".to_string())]),
+            link_terms: true,
+            ..Options::default()
+        };
+        let content = "{:.theorem #thm}\nTrue.\n\n{:.term name=Idempotent}\nSafe to repeat.\n\n\
+                       <!-- classy:off -->\n\n{{#classy-index theorem}}\n\n{{#classy-glossary}}\n\n\
+                       By [@thm] or [@nope], idempotent.\n\n<!-- classy:on -->\n\nBy [@thm].\n";
+        let output = transform(content, &options).unwrap();
+        assert!(output.content.ends_with(
+            "<!-- classy:off -->\n\n{{#classy-index theorem}}\n\n{{#classy-glossary}}\n\n\
+             By \\[@thm\\] or \\[@nope\\], idempotent.\n\n<!-- classy:on -->\n\nBy [Theorem 1](#thm)."
+        ));
+        assert!(output.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn handlers_replace_blocks() {
+        let todo =
+            |_: &Block<'_>| -> Result<Option<String>, HandlerError> { Ok(Some(String::new())) };
+        let shout = |block: &Block<'_>| -> Result<Option<String>, HandlerError> {
+            assert_eq!(block.kind, BlockKind::Paragraph);
+            assert!(matches!(block.events[0], Event::Start(Tag::Paragraph)));
+            let tag = block.annotation.attribute("tag").ok_or("no tag")?;
+            Ok(Some(format!(
+                "<{tag}>\n{}\n</{tag}>",
+                block.markdown.to_uppercase(),
+                tag = tag
+            )))
+        };
+        let options = Classy::builder()
+            .handler("todo", todo)
+            .handler("shout", shout)
+            .build()
+            .options;
+
+        let content =
+            "{:.todo}\n- later\n\n> {:.shout tag=\"b\"}\n> hi\n> there\n\n{:.other}\ntext\n";
+        let output = transform(content, &options).unwrap();
+        assert_eq!(
+            output.content,
+            "\n > \n > <b>\n > HI\n > THERE\n > </b>\n\n<div class=\"other\">\n\ntext\n\n</div>"
+        );
+
+        let error = transform("{:.shout}\ntext", &options).unwrap_err();
+        assert_eq!(error.to_string(), "the handler for `.shout` failed: no tag");
+    }
+
+    #[test]
+    fn mdbook_versions() {
+        let built = Version::parse(mdbook::MDBOOK_VERSION).unwrap();
+        for compatible in [
+            format!("0.{}.0", built.minor),
+            format!("0.{}.999", built.minor),
+        ] {
+            assert!(check_mdbook_version(&compatible).is_ok(), "{}", compatible);
+        }
+        for incompatible in [format!("0.{}.0", built.minor + 1), "1.0.0".to_string()] {
+            let message = check_mdbook_version(&incompatible).unwrap_err().to_string();
+            assert!(message.contains("incompatible"), "{}", message);
+        }
+        assert!(check_mdbook_version("not a version").is_err());
+    }
+
     #[test]
     fn no_change_preprocessor_run() {
         let content = r#####"# Chapter 1\n"#####;
@@ -0,0 +1,172 @@
+use crate::annotation::{escape, Annotation};
+
+/// The classes of tab groups and their panels, which classy styles itself.
+pub(crate) const CLASSES: [&str; 2] = ["tabs", "tab"];
+
+/// Whether an annotated block is a tab, to be shown as a panel of the `tabs` container it's in.
+pub(crate) fn is_tab(annotation: &Annotation) -> bool {
+    annotation.classes.iter().any(|class| class == "tab")
+}
+
+/// Styles for the tab list, using the colours of whichever mdbook theme is selected.
+const CSS: &str = r#"<style>
+.tabs > [role="tablist"] { display: flex; flex-wrap: wrap; border-bottom: 1px solid var(--table-border-color); }
+.tabs > [role="tablist"] > [role="tab"] { margin-bottom: -1px; padding: 0.5em 1em; border: none; border-bottom: 2px solid transparent; background: none; color: var(--fg); font: inherit; cursor: pointer; }
+.tabs > [role="tablist"] > [role="tab"][aria-selected="true"] { border-bottom-color: var(--links); color: var(--links); }
+</style>"#;
+
+/// Selects tabs on click, or with the arrow, Home and End keys, in every group on the page that
+/// has a tab with the same title.
+const JS: &str = r#"<script>
+(function () {
+    if (window.classyTabs) return;
+    window.classyTabs = true;
+    var selector = ".tabs > [role=tablist] > [role=tab]";
+    function select(title) {
+        document.querySelectorAll(".tabs > [role=tablist]").forEach(function (list) {
+            var tabs = Array.prototype.slice.call(list.children);
+            if (!tabs.some(function (tab) { return tab.dataset.classyTab === title; })) return;
+            tabs.forEach(function (tab) {
+                var selected = tab.dataset.classyTab === title;
+                tab.setAttribute("aria-selected", selected);
+                tab.tabIndex = selected ? 0 : -1;
+                document.getElementById(tab.getAttribute("aria-controls")).hidden = !selected;
+            });
+        });
+    }
+    document.addEventListener("click", function (event) {
+        var tab = event.target.closest(selector);
+        if (tab) select(tab.dataset.classyTab);
+    });
+    document.addEventListener("keydown", function (event) {
+        var tab = event.target.closest(selector);
+        if (!tab) return;
+        var tabs = Array.prototype.slice.call(tab.parentNode.children);
+        var i = tabs.indexOf(tab);
+        var next = { ArrowLeft: i - 1, ArrowRight: i + 1, Home: 0, End: tabs.length - 1 }[event.key];
+        if (next === undefined) return;
+        // mdbook turns pages with the arrow keys, in a listener added after this one.
+        event.preventDefault();
+        event.stopImmediatePropagation();
+        tab = tabs[(next + tabs.length) % tabs.length];
+        tab.focus();
+        select(tab.dataset.classyTab);
+    });
+})();
+</script>"#;
+
+/// A `tabs` container and the titles of its tabs, which give each tab its label.
+pub(crate) struct Group<'a> {
+    annotation: &'a Annotation,
+    titles: Vec<String>,
+    /// The ids of the panels: the tabs' own, or made up from the group's.
+    panels: Vec<String>,
+    /// The prefix of the ids of the tab buttons, and of panels without an id.
+    id: String,
+}
+
+impl<'a> Group<'a> {
+    /// The `number`th group of tabs in a chapter, with the `tabs` annotated in it.
+    pub fn new(annotation: &'a Annotation, tabs: &[&Annotation], number: usize) -> Group<'a> {
+        let titles = tabs
+            .iter()
+            .enumerate()
+            .map(|(k, tab)| match tab.attribute("title") {
+                Some(title) => title.to_string(),
+                None => format!("Tab {}", k + 1),
+            })
+            .collect();
+        let id = match &annotation.id {
+            Some(id) => id.clone(),
+            None => format!("classy-tabs-{}", number),
+        };
+        let panels = tabs
+            .iter()
+            .enumerate()
+            .map(|(k, tab)| match &tab.id {
+                Some(panel) => panel.clone(),
+                None => format!("{}-panel-{}", id, k + 1),
+            })
+            .collect();
+        Group {
+            annotation,
+            titles,
+            panels,
+            id,
+        }
+    }
+
+    /// The container element and its tab list, preceded by the styles and script when `assets`.
+    pub fn open(&self, assets: bool) -> String {
+        let mut html = vec![];
+        if assets {
+            html.extend([CSS, JS]);
+        }
+        let mut lines = vec![
+            format!("<div{}>", self.annotation.html_attributes()),
+            "<div role=\"tablist\">".to_string(),
+        ];
+        for (k, title) in self.titles.iter().enumerate() {
+            let selected = k == 0;
+            lines.push(format!(
+                "<button type=\"button\" role=\"tab\" id=\"{}-tab-{}\" aria-controls=\"{}\" \
+                 aria-selected=\"{}\" tabindex=\"{}\" data-classy-tab=\"{title}\">{title}</button>",
+                escape(&self.id),
+                k + 1,
+                escape(&self.panels[k]),
+                selected,
+                if selected { 0 } else { -1 },
+                title = escape(title),
+            ));
+        }
+        lines.push("</div>".to_string());
+        html.extend(lines.iter().map(String::as_str));
+        html.join("\n")
+    }
+
+    /// The element opening the `k`th tab's panel, which is hidden unless it's the first.
+    pub fn panel(&self, k: usize, tab: &Annotation) -> String {
+        let mut element = tab.clone();
+        element.id = Some(self.panels[k].clone());
+        element.attributes.retain(|(name, _)| name != "title");
+        element.attributes.extend([
+            ("role".to_string(), "tabpanel".to_string()),
+            (
+                "aria-labelledby".to_string(),
+                format!("{}-tab-{}", self.id, k + 1),
+            ),
+            ("data-classy-tab".to_string(), self.titles[k].clone()),
+            ("tabindex".to_string(), "0".to_string()),
+        ]);
+        let hidden = if k == 0 { "" } else { " hidden" };
+        format!("<div{}{}>", element.html_attributes(), hidden)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation;
+
+    #[test]
+    fn tabs_and_panels() {
+        let tabs = annotation::parse("{:.tabs .wide}").unwrap();
+        let rust = annotation::parse("{:.tab title=Rust}").unwrap();
+        let python = annotation::parse("{:.tab #py}").unwrap();
+        let group = Group::new(&tabs, &[&rust, &python], 2);
+        assert_eq!(
+            group.open(false),
+            "<div class=\"tabs wide\">\n<div role=\"tablist\">\n\
+             <button type=\"button\" role=\"tab\" id=\"classy-tabs-2-tab-1\" aria-controls=\"classy-tabs-2-panel-1\" \
+             aria-selected=\"true\" tabindex=\"0\" data-classy-tab=\"Rust\">Rust</button>\n\
+             <button type=\"button\" role=\"tab\" id=\"classy-tabs-2-tab-2\" aria-controls=\"py\" \
+             aria-selected=\"false\" tabindex=\"-1\" data-classy-tab=\"Tab 2\">Tab 2</button>\n</div>"
+        );
+        assert!(group.open(true).starts_with("<style>\n"));
+        assert_eq!(
+            group.panel(1, &python),
+            "<div id=\"py\" class=\"tab\" role=\"tabpanel\" aria-labelledby=\"classy-tabs-2-tab-2\" \
+             data-classy-tab=\"Tab 2\" tabindex=\"0\" hidden>"
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use crate::config::Options;
+use crate::handler::{Block, ClassHandler, HandlerError};
+use handlebars::Handlebars;
+use mdbook::errors::Error;
+use mdbook::utils::render_markdown;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Renders blocks with a Handlebars template from the book, such as `theme/classy/callout.hbs`.
+///
+/// The template gets the block's `class`, `classes`, `id` and `attributes`, its `markdown`, its
+/// `content` rendered to html, and the `chapter`'s `name`, `path` and `number`:
+///
+/// ```handlebars
+/// <div class="callout {{attributes.kind}}">
+/// <div class="callout-title">{{attributes.title}}</div>
+/// {{{content}}}
+/// </div>
+/// ```
+pub struct TemplateHandler {
+    templates: Arc<Handlebars<'static>>,
+    name: String,
+}
+
+impl ClassHandler for TemplateHandler {
+    fn handle(&self, block: &Block<'_>) -> Result<Option<String>, HandlerError> {
+        let attributes: BTreeMap<_, _> = block.annotation.attributes.iter().cloned().collect();
+        let data = json!({
+            "class": block.class,
+            "classes": block.annotation.classes,
+            "id": block.annotation.id,
+            "attributes": attributes,
+            "markdown": block.markdown,
+            "content": render_markdown(block.markdown, false),
+            "chapter": block.chapter,
+        });
+        let html = self.templates.render(&self.name, &data)?;
+
+        // A blank line would end the html block, and markdown would take over. An empty comment
+        // keeps it going, and doesn't change what's shown, even inside a `<pre>`.
+        let html: Vec<_> = html
+            .trim_end()
+            .lines()
+            .map(|line| {
+                if line.trim().is_empty() {
+                    "<!-- -->"
+                } else {
+                    line
+                }
+            })
+            .collect();
+        Ok(Some(html.join("\n")))
+    }
+}
+
+/// Register a [`TemplateHandler`] for each template configured in `options`, reading them
+/// relative to the book's `root`.
+///
+/// Templates come from `template-dir` (`theme/classy/<class>.hbs` by default) and the
+/// `templates` table. Handlers set in code take precedence.
+pub fn register_templates(options: &mut Options, root: &Path) -> Result<(), Error> {
+    let mut files = BTreeMap::new();
+    let dir = root.join(&options.template_dir);
+    if dir.is_dir() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "hbs") {
+                if let Some(class) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    files.insert(class.to_string(), path.clone());
+                }
+            }
+        }
+    }
+    for (class, file) in &options.templates {
+        files.insert(class.clone(), root.join(file));
+    }
+
+    let mut templates = Handlebars::new();
+    for (class, file) in &files {
+        templates
+            .register_template_file(class, file)
+            .map_err(|e| Error::new(e).context(format!("loading {}", file.display())))?;
+    }
+    let templates = Arc::new(templates);
+    for class in files.keys() {
+        if options.handlers.get(class).is_none() {
+            options.handlers.insert(
+                class.clone(),
+                TemplateHandler {
+                    templates: templates.clone(),
+                    name: class.clone(),
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Strategy;
+    use crate::handler::ChapterInfo;
+    use crate::preprocessor::transform_chapter;
+
+    #[test]
+    fn renders_templates() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("theme/classy");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("callout.hbs"),
+            "<aside class=\"callout\">\n<b>{{attributes.title}}</b> ({{chapter.number}})\n\n{{{content}}}</aside>\n",
+        )
+        .unwrap();
+        fs::write(root.path().join("other.hbs"), "<hr id=\"{{id}}\">").unwrap();
+
+        let mut options = Options::default();
+        options
+            .templates
+            .insert("rule".to_string(), "other.hbs".into());
+        register_templates(&mut options, root.path()).unwrap();
+
+        let chapter = ChapterInfo {
+            name: "Intro".to_string(),
+            path: Some("intro.md".into()),
+            number: Some("1.2.".to_string()),
+        };
+        let content = "{:.callout title=\"A & B\"}\nSome *text*.\n\n{:.rule #end}\nThe end.\n";
+        let output =
+            transform_chapter(content, Some(&chapter), None, &options, Strategy::Wrap).unwrap();
+        assert_eq!(
+            output.content,
+            "<aside class=\"callout\">\n<b>A &amp; B</b> (1.2.)\n<!-- -->\n<p>Some <em>text</em>.</p>\n</aside>\n\n<hr id=\"end\">"
+        );
+    }
+}
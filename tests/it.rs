@@ -12,8 +12,436 @@ fn verify_mdbook_cli_no_support_pdf() {
     cmd.args(["supports", "pdf"]).assert().failure();
 }
 
+#[test]
+fn verify_mdbook_cli_supports_every_renderer_when_excluding() {
+    let book = tempfile::tempdir().unwrap();
+    std::fs::write(
+        book.path().join("book.toml"),
+        "[preprocessor.classy]\nexclude-classes = [\"internal\"]\n",
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.current_dir(book.path())
+        .args(["supports", "pdf"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.env(
+        "MDBOOK_PREPROCESSOR__CLASSY__EXCLUDE_CLASSES",
+        "[\"internal\"]",
+    )
+    .args(["supports", "pdf"])
+    .assert()
+    .success();
+}
+
 #[test]
 fn verify_mdbook_cli_preprocessor_no_book() {
     let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
     cmd.assert().failure();
 }
+
+#[test]
+fn verify_mdbook_cli_check_clean_book() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["check", "examples/red_and_orange"])
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn verify_mdbook_cli_check_reports_errors() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["check", "tests/books/lint"])
+        .assert()
+        .failure()
+        .stdout(
+            "tests/books/lint/src/chapter_1.md:6:1: warning[unknown-class]: unknown class `missing`: no stylesheet defines it \
+             (fix: define `.missing` in a stylesheet listed in `output.html.additional-css`)\n\
+             tests/books/lint/src/chapter_1.md:9:1: error[unsafe-attribute]: unsafe attribute `onclick`: event handler attributes are not allowed \
+             (fix: remove `onclick=...`)\n\
+             tests/books/lint/src/chapter_1.md:12:8: error[malformed-annotation]: unclosed annotation, expected `}` \
+             (fix: add a closing `}`)\n",
+        );
+}
+
+#[test]
+fn verify_mdbook_cli_check_sarif() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd
+        .args(["check", "tests/books/lint", "--format", "sarif"])
+        .output()
+        .unwrap();
+    let log: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let results = log["runs"][0]["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[2]["ruleId"], "malformed-annotation");
+}
+
+#[test]
+fn verify_mdbook_cli_check_and_stats_containers() {
+    let book = tempfile::tempdir().unwrap();
+    std::fs::create_dir(book.path().join("src")).unwrap();
+    std::fs::write(book.path().join("book.toml"), "[book]\n").unwrap();
+    std::fs::write(
+        book.path().join("src/SUMMARY.md"),
+        "[Chapter](chapter.md)\n",
+    )
+    .unwrap();
+    std::fs::write(
+        book.path().join("src/chapter.md"),
+        "::: boxed\n\nText.\n\n:::\n\n::: {.x\n\n:::\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd.arg("check").arg(book.path()).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rules: Vec<_> = stdout
+        .lines()
+        .map(|line| line.split(['[', ']']).nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        rules,
+        ["unknown-class", "malformed-annotation", "unmatched-fence"]
+    );
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.arg("stats")
+        .arg(book.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "class  boxed  chapter.md:1:1  container",
+        ));
+}
+
+/// A book with `book.toml` and one chapter, `chapter.md`.
+fn scratch_book(config: &str, chapter: &str) -> tempfile::TempDir {
+    let book = tempfile::tempdir().unwrap();
+    std::fs::create_dir(book.path().join("src")).unwrap();
+    std::fs::write(book.path().join("book.toml"), config).unwrap();
+    std::fs::write(
+        book.path().join("src/SUMMARY.md"),
+        "[Chapter](chapter.md)\n",
+    )
+    .unwrap();
+    std::fs::write(book.path().join("src/chapter.md"), chapter).unwrap();
+    book
+}
+
+#[test]
+fn verify_mdbook_cli_classy_styles_its_own_classes() {
+    let book = scratch_book(
+        "[book]\n[preprocessor.classy]\nadmonitions = true\nexclude-classes = [\"internal\"]\n",
+        "{:.warning}\nCareful.\n\n{:.internal}\nSecret.\n\n::: tabs\n\n{:.tab title=One}\nOne.\n\n{:.tab title=Two}\nTwo.\n\n:::\n\n\
+         ::: columns\n\nLeft.\n\n:::\n\n\
+         {:.custom}\nText.\n",
+    );
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd.arg("check").arg(book.path()).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("unknown class `custom`"));
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.arg("css")
+        .arg(book.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::ends_with(".custom {\n}\n"))
+        .stdout(predicates::str::contains("/*").count(1));
+}
+
+#[test]
+fn verify_mdbook_cli_check_undefined_references() {
+    let book = scratch_book(
+        "[book]\n[preprocessor.classy.numbered]\ntheorem = \"Theorem\"\n",
+        "{:.theorem #thm}\nTrue.\n\nBy [@thm] and [@nope].\n",
+    );
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd.arg("check").arg(book.path()).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let undefined: Vec<_> = stdout
+        .lines()
+        .filter(|line| line.contains("[undefined-reference]"))
+        .collect();
+    assert_eq!(undefined.len(), 1);
+    assert!(undefined[0].contains("chapter.md:4:"));
+    assert!(undefined[0].contains("nope"));
+}
+
+#[test]
+fn verify_mdbook_cli_check_front_matter() {
+    let book = tempfile::tempdir().unwrap();
+    std::fs::create_dir(book.path().join("src")).unwrap();
+    std::fs::write(
+        book.path().join("book.toml"),
+        "[book]\n[output.html]\nadditional-css = [\"style.css\"]\n",
+    )
+    .unwrap();
+    std::fs::write(book.path().join("style.css"), ".note {}\n").unwrap();
+    std::fs::write(
+        book.path().join("src/SUMMARY.md"),
+        "[A](a.md)\n[B](b.md)\n[C](c.md)\n[D](d.md)\n",
+    )
+    .unwrap();
+    for (name, content) in [
+        (
+            "a.md",
+            "+++\n[aliases]\ntip = \"note\"\n+++\n{:.tip}\nText.\n",
+        ),
+        ("b.md", "+++\nclassy = false\n+++\n{:.broken\nText.\n"),
+        ("c.md", "+++\ncolour = \"red\"\n+++\nText.\n"),
+        ("d.md", "+++\nclasses = \"wide\"\n+++\nText.\n"),
+    ] {
+        std::fs::write(book.path().join("src").join(name), content).unwrap();
+    }
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd.arg("check").arg(book.path()).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let found: Vec<_> = stdout
+        .lines()
+        .map(|line| {
+            let file = line.split(':').next().unwrap().rsplit('/').next().unwrap();
+            (file, line.split(['[', ']']).nth(1).unwrap())
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [("c.md", "invalid-front-matter"), ("d.md", "unknown-class")]
+    );
+}
+
+#[test]
+fn verify_mdbook_cli_fmt_check() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["fmt", "--check", "examples/red_and_orange"])
+        .assert()
+        .failure()
+        .stdout("examples/red_and_orange/src/chapter_1.md needs formatting\n");
+}
+
+#[test]
+fn verify_mdbook_cli_render_stdin() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.arg("render")
+        .write_stdin("{:.red}\nred text\n")
+        .assert()
+        .success()
+        .stdout("<div class=\"red\">\n\nred text\n\n</div>\n");
+}
+
+#[test]
+fn verify_mdbook_cli_render_html() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args([
+        "render",
+        "--html",
+        "--config",
+        "examples/red_and_orange/book.toml",
+        "examples/red_and_orange/src/chapter_1.md",
+    ])
+    .assert()
+    .success()
+    .stdout(predicates::str::contains(
+        "<div class=\"red big\">\n<p>You can write <strong>Markdown</strong>",
+    ));
+}
+
+#[test]
+fn verify_mdbook_cli_render_bad_option() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["render", "--set", "diagnostics-format=yaml"])
+        .write_stdin("text")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn verify_mdbook_cli_stats() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["stats", "tests/books/lint"])
+        .assert()
+        .success()
+        .stdout(
+            "kind   name     uses  chapters          blocks\n\
+             class  missing  1     chapter_1.md (1)  paragraph (1)\n\
+             class  note     2     chapter_1.md (2)  paragraph (2)\n\
+             \n\
+             kind   name     location          block\n\
+             class  missing  chapter_1.md:6:1  paragraph\n\
+             class  note     chapter_1.md:3:1  paragraph\n\
+             class  note     chapter_1.md:9:1  paragraph\n",
+        );
+}
+
+#[test]
+fn verify_mdbook_cli_inventory_json() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd
+        .args(["inventory", "--json", "examples/red_and_orange"])
+        .output()
+        .unwrap();
+    let inventory: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(inventory["classes"]["red"]["count"], 1);
+    assert_eq!(inventory["classes"]["red"]["chapters"]["chapter_1.md"], 1);
+}
+
+#[test]
+fn verify_mdbook_cli_init() {
+    let book = tempfile::tempdir().unwrap();
+    std::fs::write(
+        book.path().join("book.toml"),
+        "[book]\ntitle = \"Example\" # keep me\n",
+    )
+    .unwrap();
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.arg("init").arg(book.path()).assert().success().stdout(
+        "added [preprocessor.classy] to book.toml\n\
+             added classy.css to output.html.additional-css in book.toml\n\
+             created classy.css\n",
+    );
+    let config = std::fs::read_to_string(book.path().join("book.toml")).unwrap();
+    assert!(config.starts_with("[book]\ntitle = \"Example\" # keep me\n"));
+    assert!(config.contains("[preprocessor.classy]\nafter = [\"links\"]\n"));
+    assert!(book.path().join("classy.css").exists());
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.arg("init")
+        .arg(book.path())
+        .assert()
+        .success()
+        .stdout(predicates::str::ends_with("already uses classy\n"));
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["init", "--admonitions"])
+        .arg(book.path())
+        .assert()
+        .success()
+        .stdout(
+            "set preprocessor.classy.admonitions in book.toml\n\
+             added classy-admonitions.css to output.html.additional-css in book.toml\n\
+             created classy-admonitions.css\n",
+        );
+}
+
+#[test]
+fn verify_mdbook_cli_css_append() {
+    let dir = tempfile::tempdir().unwrap();
+    let stylesheet = dir.path().join("extra.css");
+    std::fs::write(&stylesheet, ".other { color: red; }").unwrap();
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["css", "tests/books/lint", "--append"])
+        .arg(&stylesheet)
+        .assert()
+        .success()
+        .stdout(format!("added 1 rule to {}\n", stylesheet.display()));
+    assert_eq!(
+        std::fs::read_to_string(&stylesheet).unwrap(),
+        ".other { color: red; }\n\n/* 1 use, first in chapter_1.md:6 */\n.missing {\n}\n"
+    );
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["css", "tests/books/lint", "--append"])
+        .arg(&stylesheet)
+        .assert()
+        .success()
+        .stdout(predicates::str::ends_with("already styles every class\n"));
+}
+
+fn preprocessor_input(mdbook_version: &str) -> String {
+    serde_json::json!([
+        {
+            "root": ".",
+            "config": {"book": {"src": "src"}, "preprocessor": {"classy": {}}},
+            "renderer": "html",
+            "mdbook_version": mdbook_version
+        },
+        {"sections": [], "__non_exhaustive": null}
+    ])
+    .to_string()
+}
+
+#[test]
+fn verify_mdbook_cli_version_check() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.write_stdin(preprocessor_input(mdbook::MDBOOK_VERSION))
+        .assert()
+        .success()
+        .stderr("");
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.write_stdin(preprocessor_input("99.0.0"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "mdbook 99.0.0 is incompatible with this mdbook-classy",
+        ));
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.arg("--skip-version-check")
+        .write_stdin(preprocessor_input("99.0.0"))
+        .assert()
+        .success();
+}
+
+#[test]
+fn verify_mdbook_cli_explain_json() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    let output = cmd
+        .args(["debug", "--json"])
+        .write_stdin("{:.red}\nred text")
+        .output()
+        .unwrap();
+    let explanation: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(explanation["annotations"][0]["block"], "paragraph");
+    assert_eq!(
+        explanation["plan"][0],
+        serde_json::json!({"step": "html", "html": "<div class=\"red\">"})
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn verify_mdbook_cli_command_handler() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args([
+        "render",
+        "--set",
+        r#"handlers={shout={command="sh -c 'echo LOUD'"}}"#,
+    ])
+    .write_stdin("{:.shout}\nquiet\n\nafter")
+    .assert()
+    .success()
+    .stdout("LOUD\n\nafter\n");
+
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args([
+        "render",
+        "--set",
+        r#"handlers={shout={command="sh -c 'exit 1'"}}"#,
+    ])
+    .write_stdin("{:.shout}\nquiet")
+    .assert()
+    .failure()
+    .stderr(predicates::str::contains(
+        "the handler for `.shout` failed: `sh -c 'exit 1'` failed with exit status: 1",
+    ));
+}
+
+#[test]
+fn verify_mdbook_cli_render_exclude_classes() {
+    let mut cmd = Command::cargo_bin("mdbook-classy").unwrap();
+    cmd.args(["render", "--set", "exclude-classes=[\"internal\"]"])
+        .write_stdin("{:.internal}\nsecret\n\n{:.red}\nred text\n")
+        .assert()
+        .success()
+        .stdout("<div class=\"red\">\n\nred text\n\n</div>\n");
+}